@@ -1,7 +1,8 @@
 use crate::concurrent_heap::Item::{Available, Empty, InProgress};
+use crate::render;
 use crossbeam_utils::CachePadded;
 use parking_lot::{Condvar, Mutex};
-use std::fmt::Pointer;
+use std::fmt::{self, Debug, Display};
 use std::thread::ThreadId;
 
 // Algorithm reference: https://www.cs.rochester.edu/u/scott/papers/1996_IPL_heaps.pdf
@@ -216,6 +217,38 @@ impl<T: Ord> ConcurrentHeap<T> {
         *self.size.lock()
     }
 }
+
+impl<T: Ord + Debug> ConcurrentHeap<T> {
+    /// Renders the heap in Graphviz DOT format, one box per slot showing its
+    /// state. See `slot_labels` for which slots are included.
+    pub fn to_dot(&self) -> String {
+        render::to_dot("heap", &self.slot_labels(), 2)
+    }
+
+    /// Labels every slot up to the last non-empty one with its state. Slots
+    /// are locked one at a time, so under concurrent pushes and pops this is
+    /// not a consistent snapshot.
+    fn slot_labels(&self) -> Vec<String> {
+        let mut labels: Vec<String> = Vec::new();
+        let mut len = 0;
+        for (i, slot) in self.data.iter().enumerate() {
+            let slot = slot.lock();
+            if !matches!(*slot, Empty) {
+                len = i + 1;
+            }
+            labels.push(format!("{:?}", *slot));
+        }
+        labels.truncate(len);
+        labels
+    }
+}
+
+/// Draws the heap as a tree, one `[slot] state` line per slot.
+impl<T: Ord + Debug> Display for ConcurrentHeap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render::write_tree(f, &self.slot_labels(), 2)
+    }
+}
 #[cfg(test)]
 mod tests {
     use crate::concurrent_heap::{ConcurrentHeap, Item};
@@ -240,6 +273,27 @@ mod tests {
         assert_eq!(pq.pop(), 2);
     }
 
+    #[test]
+    fn test_render() {
+        let pq: ConcurrentHeap<i64> = ConcurrentHeap::new(8);
+        for v in [3, 5, 1, 4] {
+            pq.push(v);
+        }
+        *pq.data[3].lock() = Item::InProgress(1, std::thread::current().id());
+        *pq.data[2].lock() = Item::Empty;
+        let tree = format!(
+            "\
+[0] Available(5)
+├── [1] Available(4)
+│   └── [3] InProgress(1, {:?})
+└── [2] Empty
+",
+            std::thread::current().id()
+        );
+        assert_eq!(pq.to_string(), tree);
+        assert!(pq.to_dot().contains("n1 -> n3;"));
+    }
+
     #[test]
     fn test_seq() {
         let N = 1000;
@@ -251,7 +305,7 @@ mod tests {
             assert!(matches!(*pq.data[i].lock(), Item::Available(_)));
             assert_eq!(N - i, *pq.data[i].lock().get_val().unwrap());
         }
-        // println!("{:?}", pq);
+        // println!("{pq}");
         for n in (1..=N).rev() {
             assert_eq!(n, pq.pop());
             for j in 0..n - 1 {
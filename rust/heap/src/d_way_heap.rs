@@ -1,3 +1,5 @@
+use crate::render;
+use std::fmt::{self, Debug, Display};
 use std::ops::{Deref, DerefMut};
 
 #[derive(Debug)]
@@ -112,6 +114,24 @@ impl<T: Ord, const D: usize> DWayHeap<T, D> {
     }
 }
 
+impl<T: Ord + Debug, const D: usize> DWayHeap<T, D> {
+    /// Renders the heap in Graphviz DOT format, one box per element.
+    pub fn to_dot(&self) -> String {
+        render::to_dot("heap", &self.labels(), D)
+    }
+
+    fn labels(&self) -> Vec<String> {
+        self.data.iter().map(|v| format!("{v:?}")).collect()
+    }
+}
+
+/// Draws the heap as a tree, one `[index] element` line per element.
+impl<T: Ord + Debug, const D: usize> Display for DWayHeap<T, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render::write_tree(f, &self.labels(), D)
+    }
+}
+
 pub struct PeekMut<'a, T: Ord, const D: usize> {
     heap: &'a mut DWayHeap<T, D>,
 }
@@ -191,6 +211,20 @@ mod tests {
         assert_eq!(12, *pq.peek().unwrap());
     }
 
+    #[test]
+    fn test_render() {
+        let pq: DWayHeap<i32, 3> = DWayHeap::from_vec(vec![1, 2, 3, 4, 5]);
+        let tree = "\
+[0] 5
+├── [1] 2
+│   └── [4] 1
+├── [2] 3
+└── [3] 4
+";
+        assert_eq!(pq.to_string(), tree);
+        assert!(pq.to_dot().contains("n1 -> n4;"));
+    }
+
     #[test]
     fn test_seq() {
         let N = 1000;
@@ -198,7 +232,7 @@ mod tests {
         for i in (1..=N).rev() {
             pq.insert(i);
         }
-        // println!("{:?}", pq);
+        // println!("{pq}");
         for i in (1..=N).rev() {
             assert_eq!(i, pq.pop().unwrap());
         }
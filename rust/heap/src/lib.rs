@@ -1,2 +1,3 @@
 mod concurrent_heap;
 mod d_way_heap;
+mod render;
@@ -0,0 +1,76 @@
+use std::fmt::{self, Write};
+
+/// Slots holding the children of slot `i`, for a complete `d`-ary tree laid
+/// out in an array of `len` slots.
+fn children(i: usize, d: usize, len: usize) -> std::ops::Range<usize> {
+    (d * i + 1).min(len)..(d * i + d + 1).min(len)
+}
+
+/// Draws the tree of `labels` sideways, root first, one `[slot] label` line
+/// per slot.
+pub(crate) fn write_tree(f: &mut fmt::Formatter<'_>, labels: &[String], d: usize) -> fmt::Result {
+    if labels.is_empty() {
+        return writeln!(f, "(empty)");
+    }
+    // (slot, indent inherited from its ancestors, branch leading to it)
+    let mut stack = vec![(0, String::new(), "")];
+    while let Some((i, indent, branch)) = stack.pop() {
+        writeln!(f, "{indent}{branch}[{i}] {}", labels[i])?;
+        let indent = match branch {
+            "" => indent,
+            "└── " => indent + "    ",
+            _ => indent + "│   ",
+        };
+        let kids = children(i, d, labels.len());
+        let last = kids.end.saturating_sub(1);
+        for c in kids.rev() {
+            let branch = if c == last {
+                "└── "
+            } else {
+                "├── "
+            };
+            stack.push((c, indent.clone(), branch));
+        }
+    }
+    Ok(())
+}
+
+/// Renders the tree of `labels` in Graphviz DOT format, one box per slot.
+pub(crate) fn to_dot(name: &str, labels: &[String], d: usize) -> String {
+    let mut dot = format!("digraph {name} {{\n    node [shape=box];\n");
+    for (i, label) in labels.iter().enumerate() {
+        let _ = writeln!(dot, "    n{i} [label=\"[{i}]\\n{}\"];", escape(label));
+    }
+    for i in 0..labels.len() {
+        for c in children(i, d, labels.len()) {
+            let _ = writeln!(dot, "    n{i} -> n{c};");
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Escapes a label for use inside a double-quoted DOT string.
+fn escape(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("say \"hi\"\n\\"), "say \\\"hi\\\"\\n\\\\");
+    }
+}
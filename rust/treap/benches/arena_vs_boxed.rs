@@ -0,0 +1,84 @@
+//! Compares the arena-backed treap with the boxed one.
+//!
+//! Run with `cargo bench --bench arena_vs_boxed`. The number of entries
+//! defaults to 10M and can be changed with the `TREAP_BENCH_N` variable.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use treap::arena_treap::ArenaTreap;
+use treap::treap::Treap;
+
+fn keys(n: usize) -> Vec<(u64, u64)> {
+    // xorshift64*, seeded so both treaps see the same keys and priorities
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    let mut next = move || {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    };
+    (0..n).map(|_| (next(), next())).collect()
+}
+
+fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let out = f();
+    (out, start.elapsed())
+}
+
+fn report(name: &str, n: usize, insert: Duration, lookup: Duration, drop: Duration) {
+    let per_op = |d: Duration| d.as_nanos() as f64 / n as f64;
+    println!(
+        "{name:>6}: insert {:>8.2?} ({:>6.1} ns/op)  lookup {:>8.2?} ({:>6.1} ns/op)  drop {:>8.2?}",
+        insert,
+        per_op(insert),
+        lookup,
+        per_op(lookup),
+        drop,
+    );
+}
+
+fn bench_boxed(entries: &[(u64, u64)]) {
+    let (treap, insert) = time(|| {
+        let mut treap: Treap<u64, u64, u64> = Treap::default();
+        for &(k, p) in entries {
+            treap.insert(k, p, k);
+        }
+        treap
+    });
+    let (_, lookup) = time(|| {
+        for (k, _) in entries {
+            black_box(treap.get(k));
+        }
+    });
+    let (_, drop) = time(|| std::mem::drop(treap));
+    report("boxed", entries.len(), insert, lookup, drop);
+}
+
+fn bench_arena(entries: &[(u64, u64)]) {
+    let (treap, insert) = time(|| {
+        let mut treap = ArenaTreap::new();
+        for &(k, p) in entries {
+            treap.insert(k, p, k);
+        }
+        treap
+    });
+    let (_, lookup) = time(|| {
+        for (k, _) in entries {
+            black_box(treap.get(k));
+        }
+    });
+    let (_, drop) = time(|| std::mem::drop(treap));
+    report("arena", entries.len(), insert, lookup, drop);
+}
+
+fn main() {
+    let n = std::env::var("TREAP_BENCH_N")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(10_000_000);
+    let entries = keys(n);
+    println!("{n} entries");
+    bench_boxed(&entries);
+    bench_arena(&entries);
+}
@@ -0,0 +1,273 @@
+pub use crate::treap::arena::Arena;
+
+use crate::compare::Natural;
+use crate::treap::TreapNodePtr;
+
+/// Treap whose nodes live in one `Vec` owned by the treap and link to each
+/// other by `u32` index.
+///
+/// Removed entries leave their slot on a free list, which later inserts
+/// reuse, and dropping the treap frees the `Vec` in one go. The algorithms
+/// and API are those of the boxed [`Treap`](crate::treap::Treap). Each treap
+/// owns its arena, so `split_off`, `split_range`, `append` and the set
+/// operations move the smaller side's nodes into the other arena, which adds
+/// O(min(m, n)) to their cost.
+pub type ArenaTreap<K, P, V, M = (), U = (), KC = Natural, PC = Natural> =
+    TreapNodePtr<K, P, V, M, U, KC, PC, Arena>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monoid::{RangeUpdate, Sum};
+    use crate::rng::XorShift64;
+    use crate::treap::Treap;
+    use crate::treap::{Entry, TreapError};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_basic() {
+        let mut treap = ArenaTreap::new();
+        assert!(treap.is_empty());
+        assert_eq!(treap.insert(2, 5, "b"), None);
+        assert_eq!(treap.insert(1, 9, "a"), None);
+        assert_eq!(treap.insert(3, 1, "c"), None);
+        assert_eq!(treap.insert(2, 7, "B"), Some((2, "b")));
+        assert_eq!(treap.len(), 3);
+        assert_eq!(treap.get(&2), Some(&"B"));
+        assert!(!treap.contains(&4));
+        assert_eq!(treap.peek(), Some((&1, &"a")));
+        assert_eq!(treap.nth(2), Some((&3, &"c")));
+        assert_eq!(treap.rank(&3), 2);
+        assert_eq!(treap.update_priority(&3, 20), Some(1));
+        assert_eq!(treap.peek(), Some((&3, &"c")));
+        assert_eq!(treap.update_priority_with(&3, |p| *p = 0), Some(()));
+        assert_eq!(treap.update_priority(&4, 20), None);
+        assert_eq!(treap.erase(&1), Some((1, "a")));
+        assert_eq!(treap.erase(&1), None);
+        assert_eq!(treap.pop(), Some((2, "B")));
+        assert_eq!(treap.pop(), Some((3, "c")));
+        assert_eq!(treap.pop(), None);
+    }
+
+    #[test]
+    fn test_free_list_reuse() {
+        let mut treap = ArenaTreap::new();
+        for i in 0..100 {
+            treap.insert(i, i * 7 % 13, i);
+        }
+        for i in (0..100).step_by(2) {
+            assert_eq!(treap.erase(&i), Some((i, i)));
+        }
+        for i in 100..150 {
+            treap.insert(i, i * 7 % 13, i);
+        }
+        assert_eq!(treap.slots_used(), 100);
+        let expected: Vec<_> = (1..100)
+            .step_by(2)
+            .chain(100..150)
+            .map(|i| (i, i))
+            .collect();
+        assert_eq!(treap.into_vec(), expected);
+    }
+
+    #[test]
+    fn test_matches_boxed() {
+        let mut rng = XorShift64::new(11);
+        let mut arena = ArenaTreap::new();
+        let mut boxed: Treap<_, _, _> = Treap::default();
+        for _ in 0..2000 {
+            let key = rng.next_u64() % 500;
+            let priority = rng.next_u64() % 100;
+            if rng.next_u64().is_multiple_of(3) {
+                assert_eq!(arena.erase(&key), boxed.erase(&key));
+            } else {
+                assert_eq!(
+                    arena.insert(key, priority, key),
+                    boxed.insert(key, priority, key)
+                );
+            }
+            assert_eq!(arena.len(), boxed.len());
+        }
+        assert!(arena.iter().eq(boxed.iter()));
+        assert!(arena.iter().rev().eq(boxed.iter().rev()));
+        assert!(arena.range(100..=300).eq(boxed.range(100..=300)));
+        assert!(arena.range(..250).rev().eq(boxed.range(..250).rev()));
+        let mut top: Vec<_> = arena.top_k_in_range(50..450, usize::MAX).collect();
+        top.sort();
+        assert!(top.into_iter().eq(boxed.range(50..450)));
+        for key in [0, 99, 250, 499, 600] {
+            assert_eq!(arena.floor(&key), boxed.floor(&key));
+            assert_eq!(arena.successor(&key), boxed.successor(&key));
+            assert_eq!(arena.peek_in_range(key..), boxed.peek_in_range(key..));
+        }
+        while let Some(entry) = boxed.pop() {
+            assert_eq!(arena.pop(), Some(entry));
+        }
+    }
+
+    #[test]
+    fn test_split_off_append() {
+        let mut treap = ArenaTreap::new();
+        for i in 0..20 {
+            treap.insert(i, (i * 11) % 17, i);
+        }
+        let mut upper = treap.split_off(&12);
+        assert_eq!(treap.len(), 12);
+        assert!(upper.keys().copied().eq(12..20));
+        let middle = treap.split_range(4..8);
+        assert!(middle.keys().copied().eq(4..8));
+        drop(middle);
+        assert_eq!(upper.append(&mut treap), Err(TreapError::OverlappingKeys));
+        treap.append(&mut upper).unwrap();
+        assert!(upper.is_empty());
+        // the nodes moved back take the slots freed by the splits
+        assert_eq!(treap.slots_used(), 20);
+
+        // the smaller treap moves into the larger one's arena
+        let mut low: ArenaTreap<_, _, _> = (20..23).map(|i| (i, i, i)).collect();
+        let mut high: ArenaTreap<_, _, _> = (23..40).map(|i| (i, i, i)).collect();
+        low.append(&mut high).unwrap();
+        assert!(high.is_empty());
+        assert_eq!(low.slots_used(), 20);
+        treap.append(&mut low).unwrap();
+        let expected = (0..4).chain(8..40).map(|i| (i, i));
+        assert!(treap.into_vec().into_iter().eq(expected));
+    }
+
+    #[test]
+    fn test_mutation_and_entry() {
+        let mut treap = ArenaTreap::new();
+        for k in 0..10 {
+            treap.insert(k, (k * 7) % 10, k * 10);
+        }
+        for (k, v) in treap.iter_mut() {
+            *v += k;
+        }
+        for (_, v) in treap.range_mut(3..6).rev() {
+            *v = 0;
+        }
+        treap.values_mut().take(2).for_each(|v| *v += 1);
+        let expected = [1, 12, 22, 0, 0, 0, 66, 77, 88, 99];
+        assert!(treap.values().eq(expected.iter()));
+        assert!(treap.keys().copied().eq(0..10));
+        *treap.get_mut(&9).unwrap() -= 9;
+        assert_eq!(treap.get(&9), Some(&90));
+        assert_eq!(treap.get_mut(&10), None);
+
+        *treap.entry(4).or_insert(0, 5) += 1;
+        assert_eq!(treap.get(&4), Some(&1));
+        assert_eq!(*treap.entry(20).or_insert(100, 5), 5);
+        assert_eq!(treap.peek(), Some((&20, &5)));
+        match treap.entry(7) {
+            Entry::Occupied(entry) => assert_eq!(entry.remove_entry(), (7, 77)),
+            Entry::Vacant(_) => unreachable!(),
+        }
+
+        let mut cursor = treap.lower_bound_cursor_mut(&6);
+        assert_eq!(cursor.key(), Some(&6));
+        cursor.move_next();
+        assert_eq!(cursor.remove_current(), Some((8, 88)));
+        assert_eq!(cursor.insert_before(8, 1, 80), Ok(()));
+        assert_eq!(
+            cursor.insert_before(9, 1, 90),
+            Err(TreapError::UnsortedKeys)
+        );
+        assert_eq!(cursor.value(), Some(&90));
+        assert!(treap.keys().copied().eq([0, 1, 2, 3, 4, 5, 6, 8, 9, 20]));
+        assert_eq!(treap[&8], 80);
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let build = |keys: &mut dyn Iterator<Item = u32>, tag: u32| {
+            let mut arena: ArenaTreap<u32, u32, u32> = ArenaTreap::new();
+            let mut boxed: Treap<u32, u32, u32> = Treap::default();
+            for k in keys {
+                arena.insert(k, k.wrapping_mul(2654435761), tag);
+                boxed.insert(k, k.wrapping_mul(2654435761), tag);
+            }
+            (arena, boxed)
+        };
+        let evens = || build(&mut (0..300).step_by(2), 1);
+        let thirds = || build(&mut (0..300).step_by(3), 2);
+        let same = |arena: ArenaTreap<u32, u32, u32>, boxed: Treap<u32, u32, u32>| {
+            assert!(arena.into_vec().into_iter().eq(boxed.into_vec()));
+        };
+
+        let ((a, x), (b, y)) = (evens(), thirds());
+        let resolve = |_: &u32, l: u32, r: u32| l * 10 + r;
+        same(a.union(b, resolve), x.union(y, resolve));
+        let ((a, x), (b, y)) = (evens(), thirds());
+        same(a.intersection(b), x.intersection(y));
+        let ((a, x), (b, y)) = (thirds(), evens());
+        same(a.difference(b), x.difference(y));
+        let ((a, x), (b, y)) = (evens(), thirds());
+        same(a.symmetric_difference(b), x.symmetric_difference(y));
+
+        // the split-off half moves back into the slots it left
+        let (mut a, mut x) = evens();
+        let (b, y) = (a.split_off(&150), x.split_off(&150));
+        let a = a.union(b, resolve);
+        assert_eq!(a.slots_used(), 150);
+        same(a, x.union(y, resolve));
+    }
+
+    #[test]
+    fn test_aggregate_range_updates() {
+        let mut arena: ArenaTreap<u32, u32, i64, Sum<i64>, RangeUpdate<i64>> =
+            ArenaTreap::with_monoid();
+        let mut boxed: Treap<u32, u32, i64, Sum<i64>, RangeUpdate<i64>> = Treap::with_monoid();
+        for t in 0..200u32 {
+            let v = (t as i64 * 37) % 101 - 50;
+            arena.insert(t, t.wrapping_mul(2654435761), v);
+            boxed.insert(t, t.wrapping_mul(2654435761), v);
+        }
+        arena.update_range(20..120, RangeUpdate::Add(3));
+        boxed.update_range(20..120, RangeUpdate::Add(3));
+        arena.update_index_range(50..=70, RangeUpdate::Assign(-2));
+        boxed.update_index_range(50..=70, RangeUpdate::Assign(-2));
+        for range in [0..200, 10..30, 55..65, 119..121, 150..150] {
+            assert_eq!(arena.aggregate(range.clone()), boxed.aggregate(range));
+        }
+        assert_eq!(arena.lookup(&60), boxed.lookup(&60));
+        assert_eq!(arena.pop_in_range(40..80), boxed.pop_in_range(40..80));
+        arena.retain(|k, _| k % 4 != 0);
+        boxed.retain(|k, _| k % 4 != 0);
+        assert_eq!(arena.aggregate(..), boxed.aggregate(..));
+        assert!(arena.drain(100..).eq(boxed.drain(100..)));
+        assert!(arena
+            .into_iter_by_priority()
+            .eq(boxed.into_iter_by_priority()));
+    }
+
+    #[test]
+    fn test_drops_each_entry_once() {
+        let value = Rc::new(());
+        let build = || -> ArenaTreap<u32, u32, Rc<()>> {
+            (0..100u32)
+                .map(|k| (k, k.wrapping_mul(2654435761), Rc::clone(&value)))
+                .collect()
+        };
+
+        let mut treap = build();
+        let mut upper = treap.split_off(&50);
+        let middle = upper.split_range(60..70);
+        drop(treap);
+        let mut iter = upper.into_iter();
+        iter.next();
+        drop(iter);
+        assert_eq!(Rc::strong_count(&value), 11);
+        drop(middle);
+        assert_eq!(Rc::strong_count(&value), 1);
+
+        let evens = build().extract_if(|k, _| k % 2 == 0).count();
+        assert_eq!(evens, 50);
+        let union = build().union(build(), |_, v, _| v);
+        let intersection = build().intersection(build().split_off(&30));
+        assert_eq!(Rc::strong_count(&value), 171);
+        drop((union, intersection));
+        let mut cleared = build();
+        cleared.clear();
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,33 @@
+use std::cmp::Ordering;
+use std::marker::PhantomData;
+
+/// Total order a treap uses for its keys or its priorities.
+///
+/// Like [`Monoid`](crate::monoid::Monoid), implementations are usually
+/// zero-sized marker types: the order is part of the treap's type and is not
+/// stored in it.
+pub trait Compare<T: ?Sized> {
+    fn compare(a: &T, b: &T) -> Ordering;
+}
+
+/// The type's own `Ord`. As the priority order it keeps the largest priority
+/// at the root.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Natural;
+
+impl<T: Ord + ?Sized> Compare<T> for Natural {
+    fn compare(a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Reverse of another order. As the priority order, `Reverse` keeps the
+/// smallest priority at the root.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Reverse<C = Natural>(PhantomData<C>);
+
+impl<T: ?Sized, C: Compare<T>> Compare<T> for Reverse<C> {
+    fn compare(a: &T, b: &T) -> Ordering {
+        C::compare(b, a)
+    }
+}
@@ -0,0 +1,418 @@
+use crate::monoid::{Monoid, Update};
+use crate::rng::XorShift64;
+use crate::treap::{index_bounds, TreapNodePtr};
+use std::ops::{Index, IndexMut, RangeBounds};
+
+/// Keyless treap holding the sequence; positions come from subtree sizes.
+type SeqTree<V, M, U> = TreapNodePtr<(), u64, V, Mirrored<M>, SeqTag<U>>;
+
+/// Summary of a subtree read both ways, so that reversing it is a swap even
+/// when `combine` doesn't commute.
+#[derive(Clone)]
+struct Mirrored<M> {
+    forward: M,
+    backward: M,
+}
+
+impl<V, M: Monoid<V>> Monoid<V> for Mirrored<M> {
+    fn identity() -> Self {
+        Mirrored {
+            forward: M::identity(),
+            backward: M::identity(),
+        }
+    }
+
+    fn from_value(value: &V) -> Self {
+        let summary = M::from_value(value);
+        Mirrored {
+            forward: summary.clone(),
+            backward: summary,
+        }
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Mirrored {
+            forward: self.forward.combine(&other.forward),
+            backward: other.backward.combine(&self.backward),
+        }
+    }
+}
+
+/// Lazy tag of the sequence: the caller's update and whether the summaries
+/// are mirrored by a reversal. The tree mirrors the nodes themselves.
+#[derive(Clone)]
+struct SeqTag<U> {
+    update: Option<U>,
+    reverse: bool,
+}
+
+impl<V, M: Monoid<V>, U: Update<V, M>> Update<V, Mirrored<M>> for SeqTag<U> {
+    fn apply(&self, value: &mut V) {
+        if let Some(update) = &self.update {
+            update.apply(value);
+        }
+    }
+
+    fn apply_summary(&self, summary: &mut Mirrored<M>, len: usize) {
+        if let Some(update) = &self.update {
+            update.apply_summary(&mut summary.forward, len);
+            update.apply_summary(&mut summary.backward, len);
+        }
+        if self.reverse {
+            std::mem::swap(&mut summary.forward, &mut summary.backward);
+        }
+    }
+
+    fn compose(&mut self, newer: &Self) {
+        self.reverse ^= newer.reverse;
+        match (&mut self.update, &newer.update) {
+            (_, None) => {}
+            (Some(update), Some(newer)) => update.compose(newer),
+            (update, Some(newer)) => *update = Some(newer.clone()),
+        }
+    }
+}
+
+/// Sequence backed by an implicit treap: an element's position is derived from
+/// subtree sizes instead of a stored key.
+///
+/// Inserting, removing, splitting and concatenating at arbitrary positions are
+/// all O(log n) expected, which makes it usable as a rope.
+///
+/// Like the keyed treap it can keep a [`Monoid`] summary per subtree and apply
+/// lazy [`Update`]s to index ranges. Each subtree also keeps its summary in
+/// reverse order, so `combine` need not commute for `reverse` to work. As on
+/// the keyed treap, `get` needs `U = ()` and `get_mut` needs `M = ()` too.
+pub struct ImplicitTreap<V, M: Monoid<V> = (), U: Update<V, M> = ()> {
+    root: SeqTree<V, M, U>,
+    rng: XorShift64,
+}
+
+/// Constructors for a plain sequence, so that `ImplicitTreap::new()` needs no
+/// annotation. Use `with_monoid` for a summarized one.
+impl<V> ImplicitTreap<V> {
+    pub fn new() -> Self {
+        Self::with_monoid()
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_monoid_and_seed(seed)
+    }
+}
+
+impl<V, M: Monoid<V>, U: Update<V, M>> ImplicitTreap<V, M, U> {
+    /// Creates an empty sequence with summary type `M` and update type `U`.
+    pub fn with_monoid() -> Self {
+        ImplicitTreap {
+            root: TreapNodePtr::with_monoid(),
+            rng: XorShift64::from_entropy(),
+        }
+    }
+
+    pub fn with_monoid_and_seed(seed: u64) -> Self {
+        ImplicitTreap {
+            root: TreapNodePtr::with_monoid(),
+            rng: XorShift64::new(seed),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.root.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    /// Like `get`, but pushes pending range updates down the search path
+    /// first, so it also works with a lazy update type.
+    pub fn lookup(&mut self, idx: usize) -> Option<&V> {
+        self.root.value_at_mut(idx).map(|value| &*value)
+    }
+
+    /// Combines the summaries of the values at positions `range`.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn aggregate<R: RangeBounds<usize>>(&self, range: R) -> M {
+        let (start, end) = index_bounds(&range, self.len());
+        self.root.aggregate_at(start, end).forward
+    }
+
+    /// Applies `tag` to the values at positions `range`.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn update<R: RangeBounds<usize>>(&mut self, range: R, tag: U) {
+        let tag = SeqTag {
+            update: Some(tag),
+            reverse: false,
+        };
+        self.root.update_index_range(range, tag);
+    }
+
+    /// Reverses the order of the values at positions `range`.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn reverse<R: RangeBounds<usize>>(&mut self, range: R) {
+        let tag = SeqTag {
+            update: None,
+            reverse: true,
+        };
+        self.root.reverse_index_range(range, tag);
+    }
+
+    /// Inserts `value` so that it ends up at position `idx`.
+    ///
+    /// Panics if `idx > len`.
+    pub fn insert_at(&mut self, idx: usize, value: V) {
+        let len = self.len();
+        if idx > len {
+            panic!("insertion index (is {idx}) should be <= len (is {len})");
+        }
+        let priority = self.rng.next_u64();
+        self.root.insert_at_index(idx, (), priority, value);
+    }
+
+    pub fn push_back(&mut self, value: V) {
+        self.insert_at(self.len(), value)
+    }
+
+    pub fn push_front(&mut self, value: V) {
+        self.insert_at(0, value)
+    }
+
+    /// Removes and returns the element at `idx`, or None if out of bounds.
+    pub fn remove_at(&mut self, idx: usize) -> Option<V> {
+        if idx >= self.len() {
+            return None;
+        }
+        Some(self.root.remove_at_index(idx).1)
+    }
+
+    /// Splits the sequence into `[0, idx)` and `[idx, len)`.
+    ///
+    /// Panics if `idx > len`.
+    pub fn split_at(mut self, idx: usize) -> (Self, Self) {
+        let len = self.len();
+        if idx > len {
+            panic!("split index (is {idx}) should be <= len (is {len})");
+        }
+        let right = ImplicitTreap {
+            root: self.root.split_off_at_index(idx),
+            rng: XorShift64::new(self.rng.next_u64()),
+        };
+        (self, right)
+    }
+
+    /// Appends all elements of `other` after the elements of `self`.
+    pub fn concat(&mut self, mut other: Self) {
+        self.root.concat(&mut other.root);
+    }
+
+    pub fn into_vec(self) -> Vec<V> {
+        self.root.into_iter().map(|(_, value)| value).collect()
+    }
+}
+
+// Reads through `&self`, which need `U = ()`.
+impl<V, M: Monoid<V>> ImplicitTreap<V, M> {
+    pub fn get(&self, idx: usize) -> Option<&V> {
+        self.root.value_at(idx)
+    }
+}
+
+// In-place mutation, which needs `M = ()` and `U = ()`.
+impl<V> ImplicitTreap<V> {
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut V> {
+        self.root.value_at_mut(idx)
+    }
+}
+
+impl<V> Default for ImplicitTreap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V, M: Monoid<V>> Index<usize> for ImplicitTreap<V, M> {
+    type Output = V;
+
+    fn index(&self, idx: usize) -> &Self::Output {
+        let len = self.len();
+        self.get(idx).unwrap_or_else(|| {
+            panic!("index out of bounds: the len is {len} but the index is {idx}")
+        })
+    }
+}
+
+impl<V> IndexMut<usize> for ImplicitTreap<V> {
+    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
+        let len = self.len();
+        self.get_mut(idx).unwrap_or_else(|| {
+            panic!("index out of bounds: the len is {len} but the index is {idx}")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::implicit_treap::ImplicitTreap;
+    use crate::monoid::{Max, Monoid, RangeUpdate, Sum};
+
+    /// Concatenation, which doesn't commute.
+    #[derive(Clone, Debug, PartialEq)]
+    struct Text(String);
+
+    impl Monoid<char> for Text {
+        fn identity() -> Self {
+            Text(String::new())
+        }
+
+        fn from_value(value: &char) -> Self {
+            Text(value.to_string())
+        }
+
+        fn combine(&self, other: &Self) -> Self {
+            Text(self.0.clone() + &other.0)
+        }
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut seq = ImplicitTreap::with_seed(1);
+        let mut expected = Vec::new();
+        for i in 0..200 {
+            let idx = (i * 37) % (expected.len() + 1);
+            seq.insert_at(idx, i);
+            expected.insert(idx, i);
+        }
+        for i in 0..50 {
+            let idx = (i * 13) % expected.len();
+            assert_eq!(Some(expected.remove(idx)), seq.remove_at(idx));
+        }
+        assert_eq!(None, seq.remove_at(expected.len()));
+        assert_eq!(expected.len(), seq.len());
+        for (i, v) in expected.iter().enumerate() {
+            assert_eq!(*v, seq[i]);
+        }
+        seq[0] = 1000;
+        expected[0] = 1000;
+        assert_eq!(expected, seq.into_vec());
+    }
+
+    #[test]
+    fn test_split_concat() {
+        let mut seq = ImplicitTreap::with_seed(2);
+        for c in "hello world".chars() {
+            seq.push_back(c);
+        }
+        let (mut hello, world) = seq.split_at(5);
+        assert_eq!(5, hello.len());
+        assert_eq!(Some(&' '), world.get(0));
+
+        let (_, world) = world.split_at(1);
+        hello.push_front('>');
+        let mut rest = ImplicitTreap::with_seed(3);
+        rest.push_back('!');
+        let mut seq = world;
+        seq.concat(rest);
+        seq.concat(hello);
+        assert_eq!(
+            "world!>hello",
+            seq.into_vec().into_iter().collect::<String>()
+        );
+    }
+
+    #[test]
+    fn test_reverse() {
+        let mut seq = ImplicitTreap::with_seed(4);
+        let mut expected: Vec<i32> = (0..100).collect();
+        for v in &expected {
+            seq.push_back(*v);
+        }
+        for i in 0..30 {
+            let start = (i * 7) % 100;
+            let end = start + (i * 11) % (100 - start);
+            seq.reverse(start..end);
+            expected[start..end].reverse();
+            assert_eq!(expected[start], seq[start]);
+        }
+        for (i, v) in expected.iter().enumerate() {
+            assert_eq!(Some(v), seq.get(i));
+        }
+        assert_eq!(expected, seq.into_vec());
+    }
+
+    #[test]
+    fn test_reverse_ordered_summary() {
+        let mut seq: ImplicitTreap<char, Text> = ImplicitTreap::with_monoid_and_seed(7);
+        let mut expected: Vec<char> = "abcdefghijklmnopqrstuvwxyz".chars().collect();
+        for c in &expected {
+            seq.push_back(*c);
+        }
+        for i in 0..20 {
+            let start = (i * 7) % 26;
+            let end = start + (i * 11) % (26 - start);
+            seq.reverse(start..end);
+            expected[start..end].reverse();
+            let (lo, hi) = ((i * 5) % 26, 26 - i % 4);
+            assert_eq!(
+                Text(expected[lo..hi].iter().collect()),
+                seq.aggregate(lo..hi)
+            );
+        }
+    }
+
+    #[test]
+    fn test_lazy_updates() {
+        let mut sums: ImplicitTreap<i64, Sum<i64>, RangeUpdate<i64>> =
+            ImplicitTreap::with_monoid_and_seed(5);
+        let mut maxs: ImplicitTreap<i64, Max<i64>, RangeUpdate<i64>> =
+            ImplicitTreap::with_monoid_and_seed(6);
+        let mut expected: Vec<i64> = Vec::new();
+        for i in 0..64 {
+            let v = (i * 29) % 17;
+            sums.push_back(v);
+            maxs.push_back(v);
+            expected.push(v);
+        }
+        for i in 0..40usize {
+            let start = (i * 13) % 64;
+            let end = start + (i * 5) % (64 - start) + 1;
+            let update = if i % 3 == 0 {
+                RangeUpdate::Assign(i as i64)
+            } else {
+                RangeUpdate::Add(i as i64 - 20)
+            };
+            sums.update(start..end, update);
+            maxs.update(start..end, update);
+            if i % 4 == 0 {
+                sums.reverse(start..end);
+                maxs.reverse(start..end);
+                expected[start..end].reverse();
+            }
+            for v in &mut expected[start..end] {
+                match update {
+                    RangeUpdate::Add(d) => *v += d,
+                    RangeUpdate::Assign(a) => *v = a,
+                }
+            }
+
+            let (lo, hi) = ((i * 3) % 64, 64 - i % 5);
+            assert_eq!(Sum(expected[lo..hi].iter().sum()), sums.aggregate(lo..hi));
+            assert_eq!(
+                Max(expected[lo..hi].iter().max().copied()),
+                maxs.aggregate(lo..hi)
+            );
+        }
+        assert_eq!(Some(&expected[10]), sums.lookup(10));
+        assert_eq!(expected, sums.into_vec());
+    }
+
+    #[test]
+    #[should_panic(expected = "attempted to index up to maximum usize")]
+    fn test_range_end_overflow() {
+        let mut seq = ImplicitTreap::with_seed(8);
+        seq.push_back(1);
+        seq.reverse(0..=usize::MAX);
+    }
+}
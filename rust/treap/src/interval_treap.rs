@@ -0,0 +1,630 @@
+use crate::rng::XorShift64;
+use crate::treap::TreapError;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{btree_map, BTreeMap};
+use std::iter::{FusedIterator, Peekable};
+use std::ops::Range;
+
+type Link<K> = Option<Box<IntervalNode<K>>>;
+
+/// A `(start, end)` key and its index in `IntervalTreap::values`.
+type Held<K> = (K, K, usize);
+
+struct IntervalNode<K> {
+    start: K,
+    end: K,
+    /// Index of the value in `IntervalTreap::values`.
+    id: usize,
+    priority: u64,
+    /// Intervals containing `start` but not the start of any ancestor.
+    by_start: BTreeMap<(K, K), usize>,
+    /// The same intervals, latest end first.
+    by_end: BTreeMap<(Reverse<K>, K), usize>,
+    left: Link<K>,
+    right: Link<K>,
+}
+
+impl<K: Ord + Clone> IntervalNode<K> {
+    fn cmp_key(&self, start: &K, end: &K) -> Ordering {
+        (&self.start, &self.end).cmp(&(start, end))
+    }
+
+    fn hold(&mut self, (start, end, id): Held<K>) {
+        self.by_end
+            .insert((Reverse(end.clone()), start.clone()), id);
+        self.by_start.insert((start, end), id);
+    }
+
+    fn release(&mut self, start: &K, end: &K) {
+        self.by_end.remove(&(Reverse(end.clone()), start.clone()));
+        self.by_start.remove(&(start.clone(), end.clone()));
+    }
+
+    /// Moves the held intervals that end after `point` to `out`.
+    fn take_ending_after(&mut self, point: &K, out: &mut Vec<Held<K>>) {
+        while let Some(entry) = self.by_end.first_entry() {
+            if entry.key().0 .0 <= *point {
+                break;
+            }
+            let ((Reverse(end), start), id) = entry.remove_entry();
+            self.by_start.remove(&(start.clone(), end.clone()));
+            out.push((start, end, id));
+        }
+    }
+
+    /// Moves the held intervals that start at or before `point` to `out`.
+    fn take_starting_until(&mut self, point: &K, out: &mut Vec<Held<K>>) {
+        while let Some(entry) = self.by_start.first_entry() {
+            if entry.key().0 > *point {
+                break;
+            }
+            let ((start, end), id) = entry.remove_entry();
+            self.by_end.remove(&(Reverse(end.clone()), start.clone()));
+            out.push((start, end, id));
+        }
+    }
+}
+
+/// Treap of half-open intervals `start..end`, ordered by `(start, end)` and
+/// laid out as a centered interval tree.
+///
+/// Each node holds the intervals that contain its start but no start of an
+/// ancestor, once sorted by start and once by end. A point left of a node is
+/// contained by a prefix of the first list and by nothing held right of it,
+/// and the other way round, so `stabbing` reads one run per node on a single
+/// root-to-leaf path: O(log n + k) expected for k results. `overlapping` adds
+/// the intervals starting inside the window by an in-order walk, in the same
+/// bound. Inserts and removals move O(log n) held intervals in expectation,
+/// for O(log² n) expected. Priorities are drawn at random, like in
+/// `TreapMap`.
+///
+/// Empty intervals contain no point and are kept out of the tree.
+pub struct IntervalTreap<K: Ord + Clone, V> {
+    root: Link<K>,
+    /// Empty intervals by start.
+    empty: BTreeMap<K, V>,
+    /// Values of the intervals in the tree, `None` for a free slot.
+    values: Vec<Option<V>>,
+    free: Vec<usize>,
+    len: usize,
+    rng: XorShift64,
+}
+
+impl<K: Ord + Clone, V> IntervalTreap<K, V> {
+    pub fn new() -> Self {
+        Self::with_rng(XorShift64::from_entropy())
+    }
+
+    /// Creates a treap whose priorities are reproducible across runs.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_rng(XorShift64::new(seed))
+    }
+
+    fn with_rng(rng: XorShift64) -> Self {
+        IntervalTreap {
+            root: None,
+            empty: BTreeMap::new(),
+            values: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+            rng,
+        }
+    }
+
+    /// Splits into the intervals ordered before `start..end` and the rest,
+    /// moving the held intervals that contain `start` to `out`.
+    fn split(link: Link<K>, start: &K, end: &K, out: &mut Vec<Held<K>>) -> (Link<K>, Link<K>) {
+        match link {
+            None => (None, None),
+            Some(mut node) => {
+                if node.cmp_key(start, end) == Ordering::Less {
+                    node.take_ending_after(start, out);
+                    let (left, right) = Self::split(node.right.take(), start, end, out);
+                    node.right = left;
+                    (Some(node), right)
+                } else {
+                    node.take_starting_until(start, out);
+                    let (left, right) = Self::split(node.left.take(), start, end, out);
+                    node.left = right;
+                    (left, Some(node))
+                }
+            }
+        }
+    }
+
+    fn merge(left: Link<K>, right: Link<K>) -> Link<K> {
+        match (left, right) {
+            (None, rest) | (rest, None) => rest,
+            (Some(mut left), Some(mut right)) => {
+                if left.priority >= right.priority {
+                    left.right = Self::merge(left.right.take(), Some(right));
+                    Some(left)
+                } else {
+                    right.left = Self::merge(Some(left), right.left.take());
+                    Some(right)
+                }
+            }
+        }
+    }
+
+    fn find(&self, start: &K, end: &K) -> Option<&IntervalNode<K>> {
+        let mut curr = &self.root;
+        while let Some(node) = curr {
+            match node.cmp_key(start, end) {
+                Ordering::Equal => return Some(node),
+                Ordering::Less => curr = &node.right,
+                Ordering::Greater => curr = &node.left,
+            }
+        }
+        None
+    }
+
+    /// Returns the node under `link` that holds `start..end`: the first one
+    /// on the way down whose start lies in it.
+    fn holder<'a>(mut link: &'a mut Link<K>, start: &K, end: &K) -> &'a mut IntervalNode<K> {
+        loop {
+            let node = link.as_mut().expect("an interval's own node lies in it");
+            link = if node.start < *start {
+                &mut node.right
+            } else if node.start >= *end {
+                &mut node.left
+            } else {
+                return node;
+            };
+        }
+    }
+
+    /// Puts `node` below `link` where its priority belongs, taking over the
+    /// intervals held below it that contain its start.
+    fn insert_node(link: &mut Link<K>, mut node: Box<IntervalNode<K>>) {
+        match link {
+            Some(curr) if curr.priority > node.priority => {
+                let child = if curr.cmp_key(&node.start, &node.end) == Ordering::Less {
+                    &mut curr.right
+                } else {
+                    &mut curr.left
+                };
+                Self::insert_node(child, node);
+            }
+            _ => {
+                let mut moved = Vec::new();
+                (node.left, node.right) =
+                    Self::split(link.take(), &node.start, &node.end, &mut moved);
+                for held in moved {
+                    node.hold(held);
+                }
+                *link = Some(node);
+            }
+        }
+    }
+
+    /// Inserts `value` for `range`, returning the previous value stored for
+    /// exactly the same interval.
+    ///
+    /// Fails with `ReversedInterval` if `range.start > range.end`. Empty
+    /// intervals are stored but overlap nothing.
+    pub fn insert(&mut self, range: Range<K>, value: V) -> Result<Option<V>, TreapError> {
+        match range.start.cmp(&range.end) {
+            Ordering::Greater => return Err(TreapError::ReversedInterval),
+            Ordering::Equal => {
+                let old = self.empty.insert(range.start, value);
+                if old.is_none() {
+                    self.len += 1;
+                }
+                return Ok(old);
+            }
+            Ordering::Less => {}
+        }
+        if let Some(id) = self.find(&range.start, &range.end).map(|node| node.id) {
+            return Ok(self.values[id].replace(value));
+        }
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.values[id] = Some(value);
+                id
+            }
+            None => {
+                self.values.push(Some(value));
+                self.values.len() - 1
+            }
+        };
+        let node = Box::new(IntervalNode {
+            start: range.start.clone(),
+            end: range.end.clone(),
+            id,
+            priority: self.rng.next_u64(),
+            by_start: BTreeMap::new(),
+            by_end: BTreeMap::new(),
+            left: None,
+            right: None,
+        });
+        Self::insert_node(&mut self.root, node);
+        Self::holder(&mut self.root, &range.start, &range.end).hold((range.start, range.end, id));
+        self.len += 1;
+        Ok(None)
+    }
+
+    /// Unlinks the node of `start..end`, handing what it held to the nodes
+    /// that take its place.
+    fn remove_node(link: &mut Link<K>, start: &K, end: &K) {
+        let Some(node) = link else {
+            return;
+        };
+        match node.cmp_key(start, end) {
+            Ordering::Less => Self::remove_node(&mut node.right, start, end),
+            Ordering::Greater => Self::remove_node(&mut node.left, start, end),
+            Ordering::Equal => {
+                let node = *link.take().unwrap();
+                *link = Self::merge(node.left, node.right);
+                for ((start, end), id) in node.by_start {
+                    Self::holder(link, &start, &end).hold((start, end, id));
+                }
+            }
+        }
+    }
+
+    /// Removes the interval equal to `range`.
+    pub fn remove(&mut self, range: &Range<K>) -> Option<V> {
+        let removed = if range.start == range.end {
+            self.empty.remove(&range.start)
+        } else {
+            let id = self.find(&range.start, &range.end)?.id;
+            Self::holder(&mut self.root, &range.start, &range.end)
+                .release(&range.start, &range.end);
+            Self::remove_node(&mut self.root, &range.start, &range.end);
+            self.free.push(id);
+            self.values[id].take()
+        };
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    pub fn get(&self, range: &Range<K>) -> Option<&V> {
+        if range.start == range.end {
+            return self.empty.get(&range.start);
+        }
+        let node = self.find(&range.start, &range.end)?;
+        self.values[node.id].as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over all intervals ordered by `(start, end)`.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            nodes: InOrder::new(&self.root, None, None),
+            empty: self.empty.iter().peekable(),
+            values: &self.values,
+        }
+    }
+
+    /// Iterates over the intervals that share at least one point with
+    /// `range`: those with `max(start, range.start) < min(end, range.end)`.
+    /// An empty or reversed `range` overlaps nothing.
+    ///
+    /// The intervals starting before `range.start` come first, in no
+    /// particular order, then the others ordered by `(start, end)`.
+    pub fn overlapping(&self, range: Range<K>) -> Overlapping<'_, K, V> {
+        let root = if range.start < range.end {
+            &self.root
+        } else {
+            &None
+        };
+        Overlapping {
+            later: InOrder::new(root, Some(&range.start), Some(range.end)),
+            earlier: Stabbing::new(root, range.start, &self.values),
+        }
+    }
+
+    /// Iterates over the intervals that contain `point`, in no particular
+    /// order.
+    pub fn stabbing(&self, point: &K) -> Stabbing<'_, K, V> {
+        Stabbing::new(&self.root, point.clone(), &self.values)
+    }
+}
+
+impl<K: Ord + Clone, V> Default for IntervalTreap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn value<V>(values: &[Option<V>], id: usize) -> &V {
+    values[id].as_ref().expect("held interval has a value")
+}
+
+/// In-order walk over the nodes starting at or after a lower bound and
+/// before `before`.
+struct InOrder<'a, K> {
+    stack: Vec<&'a IntervalNode<K>>,
+    before: Option<K>,
+}
+
+impl<'a, K: Ord> InOrder<'a, K> {
+    fn new(mut link: &'a Link<K>, from: Option<&K>, before: Option<K>) -> Self {
+        let mut stack = Vec::new();
+        while let Some(node) = link {
+            if from.is_some_and(|from| node.start < *from) {
+                link = &node.right;
+            } else {
+                stack.push(&**node);
+                link = &node.left;
+            }
+        }
+        InOrder { stack, before }
+    }
+
+    fn peek(&self) -> Option<&'a IntervalNode<K>> {
+        self.stack.last().copied()
+    }
+}
+
+impl<'a, K: Ord> Iterator for InOrder<'a, K> {
+    type Item = &'a IntervalNode<K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        if self
+            .before
+            .as_ref()
+            .is_some_and(|before| node.start >= *before)
+        {
+            // everything left on the stack starts even later
+            self.stack.clear();
+            return None;
+        }
+        let mut link = &node.right;
+        while let Some(child) = link {
+            self.stack.push(child);
+            link = &child.left;
+        }
+        Some(node)
+    }
+}
+
+/// Iterator over all intervals of an [`IntervalTreap`] ordered by
+/// `(start, end)`, created by `IntervalTreap::iter`.
+pub struct Iter<'a, K, V> {
+    nodes: InOrder<'a, K>,
+    empty: Peekable<btree_map::Iter<'a, K, V>>,
+    values: &'a [Option<V>],
+}
+
+impl<'a, K: Ord, V> Iterator for Iter<'a, K, V> {
+    type Item = (Range<&'a K>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // an empty interval comes before the others with the same start
+        let empty_first = match (self.empty.peek(), self.nodes.peek()) {
+            (Some((start, _)), Some(node)) => **start <= node.start,
+            (empty, _) => empty.is_some(),
+        };
+        if empty_first {
+            let (start, value) = self.empty.next()?;
+            return Some((start..start, value));
+        }
+        let node = self.nodes.next()?;
+        Some((&node.start..&node.end, value(self.values, node.id)))
+    }
+}
+
+impl<K: Ord, V> FusedIterator for Iter<'_, K, V> {}
+
+enum Run<'a, K> {
+    /// Held intervals by start, read while they start at or before the point.
+    Starting(btree_map::Iter<'a, (K, K), usize>),
+    /// Held intervals by end, read while they end after the point.
+    Ending(btree_map::Iter<'a, (Reverse<K>, K), usize>),
+}
+
+/// Iterator over the intervals of an [`IntervalTreap`] containing a point,
+/// created by `IntervalTreap::stabbing`.
+pub struct Stabbing<'a, K, V> {
+    /// Next node on the path.
+    node: Option<&'a IntervalNode<K>>,
+    run: Option<Run<'a, K>>,
+    point: K,
+    values: &'a [Option<V>],
+}
+
+impl<'a, K: Ord, V> Stabbing<'a, K, V> {
+    fn new(root: &'a Link<K>, point: K, values: &'a [Option<V>]) -> Self {
+        Stabbing {
+            node: root.as_deref(),
+            run: None,
+            point,
+            values,
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Stabbing<'a, K, V> {
+    type Item = (Range<&'a K>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let point = &self.point;
+            let hit = match &mut self.run {
+                Some(Run::Starting(iter)) => iter
+                    .next()
+                    .filter(|((start, _), _)| start <= point)
+                    .map(|((start, end), id)| (start..end, *id)),
+                Some(Run::Ending(iter)) => iter
+                    .next()
+                    .filter(|((Reverse(end), _), _)| end > point)
+                    .map(|((Reverse(end), start), id)| (start..end, *id)),
+                None => None,
+            };
+            if let Some((range, id)) = hit {
+                return Some((range, value(self.values, id)));
+            }
+            let node = self.node.take()?;
+            // intervals held left of a node end at or before its start, and
+            // those held right of it start after it
+            match self.point.cmp(&node.start) {
+                Ordering::Less => {
+                    self.run = Some(Run::Starting(node.by_start.iter()));
+                    self.node = node.left.as_deref();
+                }
+                Ordering::Greater => {
+                    self.run = Some(Run::Ending(node.by_end.iter()));
+                    self.node = node.right.as_deref();
+                }
+                Ordering::Equal => self.run = Some(Run::Starting(node.by_start.iter())),
+            }
+        }
+    }
+}
+
+impl<K: Ord, V> FusedIterator for Stabbing<'_, K, V> {}
+
+/// Iterator over the intervals of an [`IntervalTreap`] overlapping a range,
+/// created by `IntervalTreap::overlapping`.
+pub struct Overlapping<'a, K, V> {
+    /// Intervals containing the range's start.
+    earlier: Stabbing<'a, K, V>,
+    /// Intervals starting inside the range.
+    later: InOrder<'a, K>,
+}
+
+impl<'a, K: Ord, V> Iterator for Overlapping<'a, K, V> {
+    type Item = (Range<&'a K>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((range, value)) = self.earlier.next() {
+            // those starting at the range's start come again from `later`
+            if *range.start < self.earlier.point {
+                return Some((range, value));
+            }
+        }
+        let node = self.later.next()?;
+        Some((&node.start..&node.end, value(self.earlier.values, node.id)))
+    }
+}
+
+impl<K: Ord, V> FusedIterator for Overlapping<'_, K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::interval_treap::IntervalTreap;
+    use crate::rng::XorShift64;
+    use crate::treap::TreapError;
+    use std::ops::Range;
+
+    /// The ranges `iter` yields, sorted by `(start, end)`.
+    fn ranges<'a, V: 'a>(iter: impl Iterator<Item = (Range<&'a u32>, &'a V)>) -> Vec<Range<u32>> {
+        let mut ranges: Vec<_> = iter.map(|(r, _)| *r.start..*r.end).collect();
+        ranges.sort_by_key(|r| (r.start, r.end));
+        ranges
+    }
+
+    #[test]
+    fn test_basic() {
+        let mut reservations = IntervalTreap::with_seed(1);
+        assert_eq!(Ok(None), reservations.insert(10..20, "a"));
+        assert_eq!(Ok(None), reservations.insert(15..30, "b"));
+        assert_eq!(Ok(None), reservations.insert(30..40, "c"));
+        assert_eq!(Ok(Some("b")), reservations.insert(15..30, "B"));
+        assert_eq!(3, reservations.len());
+        assert_eq!(Some(&"B"), reservations.get(&(15..30)));
+
+        assert_eq!(
+            vec![10..20, 15..30, 30..40],
+            ranges(reservations.overlapping(18..31))
+        );
+        assert_eq!(vec![15..30], ranges(reservations.overlapping(20..30)));
+        let hits: Vec<_> = reservations.stabbing(&30).map(|(_, v)| *v).collect();
+        assert_eq!(vec!["c"], hits);
+        assert_eq!(0, reservations.stabbing(&40).count());
+
+        // empty and reversed queries overlap nothing, nor do empty intervals
+        assert_eq!(0, reservations.overlapping(15..15).count());
+        assert_eq!(0, reservations.overlapping(30..30).count());
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 31..18;
+        assert_eq!(0, reservations.overlapping(reversed.clone()).count());
+        assert_eq!(
+            Err(TreapError::ReversedInterval),
+            reservations.insert(reversed, "x")
+        );
+        assert_eq!(Ok(None), reservations.insert(25..25, "e"));
+        assert_eq!(Ok(None), reservations.insert(15..15, "f"));
+        assert_eq!(5, reservations.len());
+        assert_eq!(Some(&"e"), reservations.get(&(25..25)));
+        assert_eq!(vec![15..30], ranges(reservations.overlapping(24..26)));
+        assert_eq!(vec![15..30], ranges(reservations.stabbing(&25)));
+        let all: Vec<_> = reservations
+            .iter()
+            .map(|(r, v)| (*r.start..*r.end, *v))
+            .collect();
+        assert_eq!(
+            vec![
+                (10..20, "a"),
+                (15..15, "f"),
+                (15..30, "B"),
+                (25..25, "e"),
+                (30..40, "c")
+            ],
+            all
+        );
+        assert_eq!(Some("e"), reservations.remove(&(25..25)));
+        assert_eq!(Some("f"), reservations.remove(&(15..15)));
+
+        assert_eq!(Some("a"), reservations.remove(&(10..20)));
+        assert_eq!(None, reservations.remove(&(10..20)));
+        assert_eq!(2, reservations.iter().count());
+    }
+
+    #[test]
+    fn test_against_brute_force() {
+        let mut rng = XorShift64::new(4);
+        let mut treap = IntervalTreap::with_seed(4);
+        let mut all: Vec<Range<u32>> = Vec::new();
+        for i in 0..3000 {
+            if i % 3 == 2 && !all.is_empty() {
+                let range = all.swap_remove(rng.next_u64() as usize % all.len());
+                assert_eq!(Some(range.start), treap.remove(&range));
+                continue;
+            }
+            let start = (rng.next_u64() % 1000) as u32;
+            let end = start + (rng.next_u64() % 50) as u32;
+            if treap.insert(start..end, start) == Ok(None) {
+                all.push(start..end);
+            }
+        }
+        all.sort_by_key(|r| (r.start, r.end));
+        assert_eq!(all.len(), treap.len());
+        let in_order: Vec<_> = treap.iter().map(|(r, _)| *r.start..*r.end).collect();
+        assert_eq!(all, in_order);
+
+        for _ in 0..200 {
+            let start = (rng.next_u64() % 1100) as u32;
+            let query = start..start + (rng.next_u64() % 80) as u32;
+            let expected: Vec<_> = all
+                .iter()
+                .filter(|r| r.start.max(query.start) < r.end.min(query.end))
+                .cloned()
+                .collect();
+            let hits: Vec<_> = treap
+                .overlapping(query.clone())
+                .map(|(r, _)| *r.start)
+                .collect();
+            // the hits starting inside the query come last, in order
+            let inside: Vec<_> = hits.iter().copied().filter(|s| *s >= query.start).collect();
+            assert!(inside.is_sorted());
+            assert!(hits.ends_with(&inside));
+            assert_eq!(expected, ranges(treap.overlapping(query)));
+
+            let expected: Vec<_> = all.iter().filter(|r| r.contains(&start)).cloned().collect();
+            assert_eq!(expected, ranges(treap.stabbing(&start)));
+        }
+    }
+}
@@ -1,4 +1,13 @@
+pub mod arena_treap;
+pub mod compare;
+pub mod implicit_treap;
+pub mod interval_treap;
+pub mod monoid;
+pub mod persistent_treap;
+mod rng;
 pub mod treap;
+pub mod treap_map;
+pub mod treap_multimap;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
@@ -0,0 +1,212 @@
+use std::ops::Add;
+
+/// Summary of a run of values, kept per subtree by the treaps.
+///
+/// `combine` must be associative and `identity` must be its neutral element;
+/// summaries are always combined in key order, so `combine` need not commute.
+pub trait Monoid<V>: Clone {
+    fn identity() -> Self;
+
+    /// Summary of a single value.
+    fn from_value(value: &V) -> Self;
+
+    fn combine(&self, other: &Self) -> Self;
+}
+
+/// No summary; the default for treaps that don't need range aggregation.
+impl<V> Monoid<V> for () {
+    fn identity() -> Self {}
+
+    fn from_value(_: &V) -> Self {}
+
+    fn combine(&self, _: &Self) -> Self {}
+}
+
+/// Update applied to every value of a subtree and pushed down to the children
+/// lazily, in the style of a lazy segment tree.
+pub trait Update<V, M>: Clone {
+    fn apply(&self, value: &mut V);
+
+    /// Updates `summary`, which covers `len` values, as if `apply` had been
+    /// called on each of them.
+    fn apply_summary(&self, summary: &mut M, len: usize);
+
+    /// Folds `newer` into `self` so that applying the result is the same as
+    /// applying `self` and then `newer`.
+    fn compose(&mut self, newer: &Self);
+}
+
+/// No updates; the default for treaps without range updates.
+impl<V, M> Update<V, M> for () {
+    fn apply(&self, _: &mut V) {}
+
+    fn apply_summary(&self, _: &mut M, _: usize) {}
+
+    fn compose(&mut self, _: &Self) {}
+}
+
+/// Range-add and range-assign, composable with each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeUpdate<T> {
+    Add(T),
+    Assign(T),
+}
+
+impl<T: Copy + Add<Output = T>> RangeUpdate<T> {
+    fn apply_value(&self, value: &mut T) {
+        match self {
+            RangeUpdate::Add(delta) => *value = *value + *delta,
+            RangeUpdate::Assign(v) => *value = *v,
+        }
+    }
+
+    fn compose_with(&mut self, newer: &Self) {
+        *self = match (*self, *newer) {
+            (RangeUpdate::Add(a), RangeUpdate::Add(b)) => RangeUpdate::Add(a + b),
+            (RangeUpdate::Assign(v), RangeUpdate::Add(b)) => RangeUpdate::Assign(v + b),
+            (_, assign) => assign,
+        }
+    }
+}
+
+/// `value` added to itself `n` times (n >= 1), by doubling.
+fn times<T: Copy + Add<Output = T>>(value: T, mut n: usize) -> T {
+    let mut acc: Option<T> = None;
+    let mut pow = value;
+    while n > 0 {
+        if n & 1 == 1 {
+            acc = Some(acc.map_or(pow, |a| a + pow));
+        }
+        n >>= 1;
+        if n > 0 {
+            pow = pow + pow;
+        }
+    }
+    acc.expect("times called with n = 0")
+}
+
+impl<T: Copy + Add<Output = T>> Update<T, ()> for RangeUpdate<T> {
+    fn apply(&self, value: &mut T) {
+        self.apply_value(value)
+    }
+
+    fn apply_summary(&self, _: &mut (), _: usize) {}
+
+    fn compose(&mut self, newer: &Self) {
+        self.compose_with(newer)
+    }
+}
+
+impl<T: Copy + Default + Add<Output = T>> Update<T, Sum<T>> for RangeUpdate<T> {
+    fn apply(&self, value: &mut T) {
+        self.apply_value(value)
+    }
+
+    fn apply_summary(&self, summary: &mut Sum<T>, len: usize) {
+        if len == 0 {
+            return;
+        }
+        match self {
+            RangeUpdate::Add(delta) => summary.0 = summary.0 + times(*delta, len),
+            RangeUpdate::Assign(v) => summary.0 = times(*v, len),
+        }
+    }
+
+    fn compose(&mut self, newer: &Self) {
+        self.compose_with(newer)
+    }
+}
+
+impl<T: Copy + Ord + Add<Output = T>> Update<T, Min<T>> for RangeUpdate<T> {
+    fn apply(&self, value: &mut T) {
+        self.apply_value(value)
+    }
+
+    fn apply_summary(&self, summary: &mut Min<T>, _: usize) {
+        if let Some(min) = &mut summary.0 {
+            self.apply_value(min)
+        }
+    }
+
+    fn compose(&mut self, newer: &Self) {
+        self.compose_with(newer)
+    }
+}
+
+impl<T: Copy + Ord + Add<Output = T>> Update<T, Max<T>> for RangeUpdate<T> {
+    fn apply(&self, value: &mut T) {
+        self.apply_value(value)
+    }
+
+    fn apply_summary(&self, summary: &mut Max<T>, _: usize) {
+        if let Some(max) = &mut summary.0 {
+            self.apply_value(max)
+        }
+    }
+
+    fn compose(&mut self, newer: &Self) {
+        self.compose_with(newer)
+    }
+}
+
+/// Sum of the values, with `T::default()` as zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sum<T>(pub T);
+
+impl<T: Copy + Default + Add<Output = T>> Monoid<T> for Sum<T> {
+    fn identity() -> Self {
+        Sum(T::default())
+    }
+
+    fn from_value(value: &T) -> Self {
+        Sum(*value)
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Sum(self.0 + other.0)
+    }
+}
+
+/// Smallest value, or None for an empty range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Min<T>(pub Option<T>);
+
+impl<T: Ord + Clone> Monoid<T> for Min<T> {
+    fn identity() -> Self {
+        Min(None)
+    }
+
+    fn from_value(value: &T) -> Self {
+        Min(Some(value.clone()))
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Min(Some(a.min(b).clone())),
+            (Some(_), None) => self.clone(),
+            (None, _) => other.clone(),
+        }
+    }
+}
+
+/// Largest value, or None for an empty range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Max<T>(pub Option<T>);
+
+impl<T: Ord + Clone> Monoid<T> for Max<T> {
+    fn identity() -> Self {
+        Max(None)
+    }
+
+    fn from_value(value: &T) -> Self {
+        Max(Some(value.clone()))
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Max(Some(a.max(b).clone())),
+            (Some(_), None) => self.clone(),
+            (None, _) => other.clone(),
+        }
+    }
+}
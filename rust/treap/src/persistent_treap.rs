@@ -0,0 +1,406 @@
+use crate::treap::TreapError;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::iter::FusedIterator;
+use std::sync::Arc;
+
+type Link<K, P, V> = Option<Arc<Node<K, P, V>>>;
+
+#[derive(Clone)]
+struct Node<K, P, V> {
+    key: K,
+    priority: P,
+    value: V,
+    size: usize,
+    left: Link<K, P, V>,
+    right: Link<K, P, V>,
+}
+
+impl<K, P, V> Node<K, P, V> {
+    fn update(&mut self) {
+        self.size = 1 + len(&self.left) + len(&self.right);
+    }
+}
+
+/// Frees the nodes no other version refers to with an explicit stack, so a
+/// degenerate tree can't overflow the stack.
+impl<K, P, V> Drop for Node<K, P, V> {
+    fn drop(&mut self) {
+        let mut stack = vec![self.left.take(), self.right.take()];
+        while let Some(link) = stack.pop() {
+            if let Some(mut node) = link.and_then(|node| Arc::try_unwrap(node).ok()) {
+                stack.push(node.left.take());
+                stack.push(node.right.take());
+            }
+        }
+    }
+}
+
+fn len<K, P, V>(link: &Link<K, P, V>) -> usize {
+    link.as_ref().map_or(0, |node| node.size)
+}
+
+/// Immutable treap whose updates return new versions.
+///
+/// Nodes are shared through `Arc`, so cloning a version is O(1) and an update
+/// copies only the O(log n) nodes on its search path; all other subtrees are
+/// shared with the version it was made from. Versions can be read from other
+/// threads while new ones are derived.
+pub struct PersistentTreap<K, P, V> {
+    root: Link<K, P, V>,
+}
+
+impl<K, P, V> Clone for PersistentTreap<K, P, V> {
+    fn clone(&self) -> Self {
+        PersistentTreap {
+            root: self.root.clone(),
+        }
+    }
+}
+
+impl<K, P, V> Default for PersistentTreap<K, P, V> {
+    fn default() -> Self {
+        PersistentTreap { root: None }
+    }
+}
+
+impl<K: Ord + Clone, P: Ord + Clone, V: Clone> PersistentTreap<K, P, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copies the nodes on the search path, sending each to the left part
+    /// (`Some(true)`), the right part (`Some(false)`) or dropping it (`None`),
+    /// and links the copies bottom-up like `Treap`'s split.
+    fn split_with<F>(root: &Link<K, P, V>, mut side: F) -> (Link<K, P, V>, Link<K, P, V>)
+    where
+        F: FnMut(&Node<K, P, V>) -> Option<bool>,
+    {
+        let mut left_spine = Vec::new();
+        let mut right_spine = Vec::new();
+        let (mut left, mut right) = (None, None);
+        let mut curr = root.clone();
+        while let Some(shared) = curr {
+            let mut node = Arc::unwrap_or_clone(shared);
+            match side(&node) {
+                Some(true) => {
+                    curr = node.right.take();
+                    left_spine.push(node);
+                }
+                Some(false) => {
+                    curr = node.left.take();
+                    right_spine.push(node);
+                }
+                None => {
+                    left = node.left.take();
+                    right = node.right.take();
+                    break;
+                }
+            }
+        }
+        while let Some(mut node) = left_spine.pop() {
+            node.right = left;
+            node.update();
+            left = Some(Arc::new(node));
+        }
+        while let Some(mut node) = right_spine.pop() {
+            node.left = right;
+            node.update();
+            right = Some(Arc::new(node));
+        }
+        (left, right)
+    }
+
+    fn split_by_key<Q>(root: &Link<K, P, V>, key: &Q) -> (Link<K, P, V>, Link<K, P, V>)
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        Self::split_with(root, |node| match node.key.borrow().cmp(key) {
+            Ordering::Less => Some(true),
+            Ordering::Equal => None,
+            Ordering::Greater => Some(false),
+        })
+    }
+
+    /// Joins two trees where every key of `left` is below every key of
+    /// `right`, copying only the merged spine.
+    fn merge_links(mut left: Link<K, P, V>, mut right: Link<K, P, V>) -> Link<K, P, V> {
+        let mut spine = Vec::new();
+        let bottom = loop {
+            match (left.take(), right.take()) {
+                (None, rest) | (rest, None) => break rest,
+                (Some(left_node), Some(right_node)) => {
+                    if left_node.priority >= right_node.priority {
+                        let mut node = Arc::unwrap_or_clone(left_node);
+                        left = node.right.take();
+                        right = Some(right_node);
+                        spine.push((node, true));
+                    } else {
+                        let mut node = Arc::unwrap_or_clone(right_node);
+                        right = node.left.take();
+                        left = Some(left_node);
+                        spine.push((node, false));
+                    }
+                }
+            }
+        };
+        spine
+            .into_iter()
+            .rev()
+            .fold(bottom, |acc, (mut node, from_left)| {
+                if from_left {
+                    node.right = acc;
+                } else {
+                    node.left = acc;
+                }
+                node.update();
+                Some(Arc::new(node))
+            })
+    }
+
+    /// Returns a version with `k` mapped to `v`, replacing any existing entry
+    /// for `k`.
+    pub fn insert(&self, k: K, p: P, v: V) -> Self {
+        let (left, right) = Self::split_by_key(&self.root, &k);
+        let node = Node {
+            key: k,
+            priority: p,
+            value: v,
+            size: 1,
+            left: None,
+            right: None,
+        };
+        let right = Self::merge_links(Some(Arc::new(node)), right);
+        PersistentTreap {
+            root: Self::merge_links(left, right),
+        }
+    }
+
+    /// Returns a version without `key`.
+    pub fn erase<Q>(&self, key: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        let (left, right) = Self::split_by_key(&self.root, key);
+        PersistentTreap {
+            root: Self::merge_links(left, right),
+        }
+    }
+
+    /// Returns the keys below `key` and the keys `>= key` as two new versions.
+    pub fn split<Q>(&self, key: &Q) -> (Self, Self)
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        let (left, right) = Self::split_with(&self.root, |node| Some(node.key.borrow() < key));
+        (
+            PersistentTreap { root: left },
+            PersistentTreap { root: right },
+        )
+    }
+
+    /// Returns the concatenation of `self` followed by `other` in O(log n).
+    ///
+    /// Every key in `self` must be less than every key in `other`; fails with
+    /// `OverlappingKeys` otherwise.
+    pub fn merge(&self, other: &Self) -> Result<Self, TreapError> {
+        let last = self.len().checked_sub(1).and_then(|i| self.nth(i));
+        if let (Some((last, _)), Some((first, _))) = (last, other.nth(0)) {
+            if last >= first {
+                return Err(TreapError::OverlappingKeys);
+            }
+        }
+        Ok(PersistentTreap {
+            root: Self::merge_links(self.root.clone(), other.root.clone()),
+        })
+    }
+}
+
+impl<K: Ord, P: Ord, V> PersistentTreap<K, P, V> {
+    fn find<Q>(&self, key: &Q) -> Option<&Node<K, P, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        let mut curr = &self.root;
+        while let Some(node) = curr {
+            match node.key.borrow().cmp(key) {
+                Ordering::Equal => return Some(node),
+                Ordering::Less => curr = &node.right,
+                Ordering::Greater => curr = &node.left,
+            }
+        }
+        None
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.find(key).map(|node| &node.value)
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.find(key).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn len(&self) -> usize {
+        len(&self.root)
+    }
+
+    /// Returns the entry with the `i`-th smallest key (0-indexed).
+    pub fn nth(&self, mut i: usize) -> Option<(&K, &V)> {
+        let mut curr = &self.root;
+        while let Some(node) = curr {
+            let left_len = len(&node.left);
+            match i.cmp(&left_len) {
+                Ordering::Less => curr = &node.left,
+                Ordering::Equal => return Some((&node.key, &node.value)),
+                Ordering::Greater => {
+                    i -= left_len + 1;
+                    curr = &node.right;
+                }
+            }
+        }
+        None
+    }
+
+    pub fn peek(&self) -> Option<(&K, &V)> {
+        self.root.as_ref().map(|node| (&node.key, &node.value))
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, P, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            remaining: self.len(),
+        };
+        iter.push_left_spine(&self.root);
+        iter
+    }
+}
+
+/// In-order iterator over a [`PersistentTreap`], created by `PersistentTreap::iter`.
+pub struct Iter<'a, K, P, V> {
+    stack: Vec<&'a Node<K, P, V>>,
+    remaining: usize,
+}
+
+impl<'a, K, P, V> Iter<'a, K, P, V> {
+    fn push_left_spine(&mut self, mut link: &'a Link<K, P, V>) {
+        while let Some(node) = link {
+            self.stack.push(node);
+            link = &node.left;
+        }
+    }
+}
+
+impl<'a, K, P, V> Iterator for Iter<'a, K, P, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.remaining -= 1;
+        self.push_left_spine(&node.right);
+        Some((&node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, P, V> ExactSizeIterator for Iter<'_, K, P, V> {}
+
+impl<K, P, V> FusedIterator for Iter<'_, K, P, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions() {
+        let empty = PersistentTreap::new();
+        let v1 = empty.insert(2, 5, "b").insert(1, 3, "a").insert(3, 4, "c");
+        let v2 = v1.insert(2, 1, "B");
+        let v3 = v2.erase(&1);
+        assert!(empty.is_empty());
+        assert_eq!(
+            v1.iter().collect::<Vec<_>>(),
+            vec![(&1, &"a"), (&2, &"b"), (&3, &"c")]
+        );
+        assert_eq!(
+            v2.iter().collect::<Vec<_>>(),
+            vec![(&1, &"a"), (&2, &"B"), (&3, &"c")]
+        );
+        assert_eq!(v3.iter().collect::<Vec<_>>(), vec![(&2, &"B"), (&3, &"c")]);
+        assert_eq!(v1.peek(), Some((&2, &"b")));
+        assert_eq!(v2.peek(), Some((&3, &"c")));
+        assert_eq!(v3.get(&1), None);
+        assert!(v2.contains(&1));
+        assert_eq!(v2.nth(1), Some((&2, &"B")));
+    }
+
+    #[test]
+    fn test_structural_sharing() {
+        let mut treap = PersistentTreap::new();
+        for i in 0..1000u32 {
+            treap = treap.insert(i, (i * 7919) % 1009, i);
+        }
+        let snapshot = treap.clone();
+        assert!(Arc::ptr_eq(
+            snapshot.root.as_ref().unwrap(),
+            treap.root.as_ref().unwrap()
+        ));
+
+        let updated = treap.insert(1000, 0, 1000);
+        let root = treap.root.as_ref().unwrap();
+        let new_root = updated.root.as_ref().unwrap();
+        assert!(!Arc::ptr_eq(root, new_root));
+        // the new key goes to the far right, so the left subtree is shared
+        assert!(Arc::ptr_eq(
+            root.left.as_ref().unwrap(),
+            new_root.left.as_ref().unwrap()
+        ));
+        assert_eq!(treap.len(), 1000);
+        assert_eq!(updated.len(), 1001);
+    }
+
+    #[test]
+    fn test_split_merge() {
+        let mut treap = PersistentTreap::new();
+        for i in 0..100u32 {
+            treap = treap.insert(i, (i * 37) % 101, i);
+        }
+        let (low, high) = treap.split(&40);
+        assert_eq!(low.len(), 40);
+        assert_eq!(high.nth(0), Some((&40, &40)));
+        assert_eq!(high.merge(&low).err(), Some(TreapError::OverlappingKeys));
+        let joined = low.merge(&high).unwrap();
+        assert!(joined.iter().eq(treap.iter()));
+        assert_eq!(treap.len(), 100);
+    }
+
+    #[test]
+    fn test_deep_drop() {
+        // each key is the smallest so far and outranks the root, giving a path
+        let mut treap = PersistentTreap::new();
+        for i in 0..100_000u32 {
+            treap = treap.insert(u32::MAX - i, i, ());
+        }
+        let older = treap.erase(&u32::MAX);
+        drop(treap);
+        assert_eq!(older.len(), 99_999);
+    }
+}
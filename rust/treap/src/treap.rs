@@ -1,192 +1,2052 @@
+pub(crate) mod arena;
+mod cursor;
+mod entry;
+mod iter;
+mod positional;
+pub(crate) mod render;
+pub(crate) mod storage;
+
+pub use cursor::{Cursor, CursorMut};
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use iter::{Iter, IterMut, Keys, Range, RangeMut, TopKInRange, Values, ValuesMut};
+pub use storage::{Boxed, Storage};
+
+use crate::compare::{Compare, Natural, Reverse};
+use crate::monoid::{Monoid, Update};
+use std::borrow::Borrow;
 use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::ops::{Bound, Index, RangeBounds};
+use storage::{Linked, Sealed};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreapError {
+    /// Every key of the appended treap must be greater than every key of `self`.
+    OverlappingKeys,
+    /// Keys must be strictly increasing, both in the input to
+    /// `from_sorted_iter` and around an entry inserted through a cursor.
+    UnsortedKeys,
+    /// An interval's start must not come after its end.
+    ReversedInterval,
+}
+
+impl fmt::Display for TreapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreapError::OverlappingKeys => write!(f, "key ranges of the treaps overlap"),
+            TreapError::UnsortedKeys => write!(f, "keys are not strictly increasing"),
+            TreapError::ReversedInterval => write!(f, "interval starts after it ends"),
+        }
+    }
+}
+
+impl Error for TreapError {}
+
+/// `KC` orders the keys and `PC` the priorities; the node whose priority is
+/// greatest under `PC` sits at the root. `S` decides where the nodes live: see
+/// [`Treap`] and [`ArenaTreap`](crate::arena_treap::ArenaTreap).
+///
+/// Pending range updates are only pushed down on mutation, so methods that
+/// read values through `&self` need `U = ()`; use `lookup` otherwise. Handing
+/// out `&mut V` would leave subtree summaries stale, so methods that do need
+/// `M = ()` as well.
+pub struct TreapNodePtr<
+    K,
+    P,
+    V,
+    M: Monoid<V> = (),
+    U: Update<V, M> = (),
+    KC = Natural,
+    PC = Natural,
+    S: Storage = Boxed,
+> {
+    root: Link<K, P, V, M, U, KC, PC, S>,
+    store: Store<K, P, V, M, U, KC, PC, S>,
+}
+
+type Handle<K, P, V, M, U, KC, PC, S> = <S as Sealed>::Handle<TreapNode<K, P, V, M, U, KC, PC, S>>;
+type Link<K, P, V, M, U, KC, PC, S> = Option<Handle<K, P, V, M, U, KC, PC, S>>;
+type Store<K, P, V, M, U, KC, PC, S> = <S as Sealed>::Store<TreapNode<K, P, V, M, U, KC, PC, S>>;
+/// Nodes taken out of a tree, or a spine being relinked.
+type Handles<K, P, V, M, U, KC, PC, S> = Vec<Handle<K, P, V, M, U, KC, PC, S>>;
+/// The parts below and above a split point.
+type TwoWay<K, P, V, M, U, KC, PC, S> = (
+    Link<K, P, V, M, U, KC, PC, S>,
+    Link<K, P, V, M, U, KC, PC, S>,
+);
+/// The parts below, at and above a split point.
+type ThreeWay<K, P, V, M, U, KC, PC, S> = (
+    Link<K, P, V, M, U, KC, PC, S>,
+    Link<K, P, V, M, U, KC, PC, S>,
+    Link<K, P, V, M, U, KC, PC, S>,
+);
+
+/// Pending work of the set operations, kept on an explicit stack.
+enum SetTask<K, P, V, M: Monoid<V>, U: Update<V, M>, KC, PC, S: Storage> {
+    /// Combine a subtree of `self` with a subtree of `other`.
+    Solve(
+        Link<K, P, V, M, U, KC, PC, S>,
+        Link<K, P, V, M, U, KC, PC, S>,
+    ),
+    /// Join the two latest results under the pivot, given whether it came
+    /// from `self` and the other side's entry with the same key.
+    Join(
+        Handle<K, P, V, M, U, KC, PC, S>,
+        bool,
+        Link<K, P, V, M, U, KC, PC, S>,
+    ),
+}
+
+struct TreapNode<K, P, V, M: Monoid<V>, U: Update<V, M>, KC, PC, S: Storage> {
+    key: K,
+    priority: P,
+    value: V,
+    /// Number of nodes in the subtree rooted at this node.
+    size: usize,
+    /// Summary of all values in the subtree, in key order.
+    summary: M,
+    /// Update still to be pushed down to the children. The node's own value
+    /// and summary already include it.
+    lazy: Option<U>,
+    /// Whether the children's subtrees still have to be mirrored. Only the
+    /// implicit treap sets this, along with a tag that mirrors the summaries.
+    reversed: bool,
+    left: Link<K, P, V, M, U, KC, PC, S>,
+    right: Link<K, P, V, M, U, KC, PC, S>,
+    /// Key and priority orders, which index links don't name.
+    order: PhantomData<fn() -> (KC, PC)>,
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC, PC, S: Storage> Linked<S>
+    for TreapNode<K, P, V, M, U, KC, PC, S>
+{
+    fn child(&self, left: bool) -> &Link<K, P, V, M, U, KC, PC, S> {
+        if left {
+            &self.left
+        } else {
+            &self.right
+        }
+    }
+
+    fn child_mut(&mut self, left: bool) -> &mut Link<K, P, V, M, U, KC, PC, S> {
+        if left {
+            &mut self.left
+        } else {
+            &mut self.right
+        }
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC, PC, S: Storage>
+    TreapNode<K, P, V, M, U, KC, PC, S>
+{
+    fn apply(&mut self, tag: &U) {
+        tag.apply(&mut self.value);
+        tag.apply_summary(&mut self.summary, self.size);
+        match &mut self.lazy {
+            Some(lazy) => lazy.compose(tag),
+            None => self.lazy = Some(tag.clone()),
+        }
+    }
+
+    /// Whether this node belongs above `other`. Ties go to `self`, which is
+    /// the left node wherever two subtrees are joined.
+    fn outranks(&self, other: &Self) -> bool
+    where
+        PC: Compare<P>,
+    {
+        PC::compare(&self.priority, &other.priority).is_ge()
+    }
+
+    /// Applies this node's pending update to a summary taken from one of its
+    /// children, which haven't seen the update yet.
+    fn pending(&self, (mut summary, count): (M, usize)) -> (M, usize) {
+        if let Some(tag) = &self.lazy {
+            tag.apply_summary(&mut summary, count);
+        }
+        (summary, count)
+    }
+
+    /// Swaps the children now and leaves mirroring their subtrees pending.
+    fn mirror(&mut self) {
+        std::mem::swap(&mut self.left, &mut self.right);
+        self.reversed ^= true;
+    }
+
+    /// Whether the children's subtrees are still to be mirrored.
+    fn reverses_below(&self) -> bool {
+        self.reversed
+    }
+}
+
+/// `Treap::new()` gives a plain treap with the natural orders; use
+/// `with_monoid` or `with_order` for the other parameters.
+///
+/// ```
+/// use treap::treap::Treap;
+///
+/// let mut treap = Treap::new();
+/// treap.insert(1, 2, 3);
+/// assert_eq!(treap.get(&1), Some(&3));
+/// ```
+pub type Treap<K, P, V, M = (), U = (), KC = Natural, PC = Natural> =
+    TreapNodePtr<K, P, V, M, U, KC, PC, Boxed>;
+
+/// Treap with the smallest priority at the root.
+pub type MinTreap<K, P, V, M = (), U = ()> = Treap<K, P, V, M, U, Natural, Reverse>;
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>>
+    From<Box<TreapNode<K, P, V, M, U, KC, PC, Boxed>>> for Treap<K, P, V, M, U, KC, PC>
+{
+    fn from(node: Box<TreapNode<K, P, V, M, U, KC, PC, Boxed>>) -> Self {
+        TreapNodePtr {
+            root: Some(node),
+            store: (),
+        }
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    TreapNodePtr<K, P, V, M, U, KC, PC, S>
+{
+    /// Builds a treap from entries with strictly increasing keys in O(n).
+    ///
+    /// Keeps the right spine of the tree built so far on a stack: each new
+    /// entry pops the lower-priority spine nodes, which become its left subtree.
+    pub fn from_sorted_iter<I>(iter: I) -> Result<Self, TreapError>
+    where
+        I: IntoIterator<Item = (K, P, V)>,
+    {
+        let mut treap = Self::empty();
+        let store = &mut treap.store;
+        let mut spine: Handles<K, P, V, M, U, KC, PC, S> = Vec::new();
+        for (key, priority, value) in iter {
+            if spine
+                .last()
+                .is_some_and(|last| KC::compare(&S::get(store, last).key, &key).is_ge())
+            {
+                // linked up so that dropping the treap frees them
+                treap.root = Self::pop_spine(store, &mut spine, |_, _| false);
+                return Err(TreapError::UnsortedKeys);
+            }
+            let node = Self::leaf(store, key, priority, value);
+            Self::push_spine(store, &mut spine, node);
+        }
+        treap.root = Self::pop_spine(store, &mut spine, |_, _| false);
+        Ok(treap)
+    }
+
+    /// Appends a childless `node` with a key above all others to the right
+    /// spine of a treap under construction.
+    fn push_spine(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        spine: &mut Handles<K, P, V, M, U, KC, PC, S>,
+        mut node: Handle<K, P, V, M, U, KC, PC, S>,
+    ) {
+        let left = Self::pop_spine(store, spine, |store, top| {
+            top.outranks(S::get(store, &node))
+        });
+        S::get_mut(store, &mut node).left = left;
+        spine.push(node);
+    }
+
+    /// Pops right-spine nodes until `stop` holds for the top one, linking each
+    /// popped node as the right child of the next.
+    fn pop_spine<F>(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        spine: &mut Handles<K, P, V, M, U, KC, PC, S>,
+        stop: F,
+    ) -> Link<K, P, V, M, U, KC, PC, S>
+    where
+        F: Fn(&Store<K, P, V, M, U, KC, PC, S>, &TreapNode<K, P, V, M, U, KC, PC, S>) -> bool,
+    {
+        let mut below = None;
+        while let Some(mut node) = spine.pop() {
+            if stop(store, S::get(store, &node)) {
+                spine.push(node);
+                break;
+            }
+            S::get_mut(store, &mut node).right = below;
+            Self::update(store, &mut node);
+            below = Some(node);
+        }
+        below
+    }
+
+    /// Walks down from `root` asking `side` where each node goes: `Some(true)`
+    /// to the left part, `Some(false)` to the right part, or `None` to detach it
+    /// and stop.
+    ///
+    /// The nodes passed on the way form the right spine of the left part and the
+    /// left spine of the right part. They are relinked bottom-up afterwards so
+    /// that degenerate trees from caller-chosen priorities can't overflow the stack.
+    fn split_with<F>(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        root: Link<K, P, V, M, U, KC, PC, S>,
+        mut side: F,
+    ) -> ThreeWay<K, P, V, M, U, KC, PC, S>
+    where
+        F: FnMut(
+            &Store<K, P, V, M, U, KC, PC, S>,
+            &TreapNode<K, P, V, M, U, KC, PC, S>,
+        ) -> Option<bool>,
+    {
+        let mut left_spine = Vec::new();
+        let mut right_spine = Vec::new();
+        let mut left_bottom = None;
+        let mut right_bottom = None;
+        let mut detached = None;
+        let mut curr = root;
+        while let Some(mut node) = curr {
+            Self::push_down(store, &mut node);
+            match side(store, S::get(store, &node)) {
+                Some(true) => {
+                    curr = S::get_mut(store, &mut node).right.take();
+                    left_spine.push(node);
+                }
+                Some(false) => {
+                    curr = S::get_mut(store, &mut node).left.take();
+                    right_spine.push(node);
+                }
+                None => {
+                    let parts = S::get_mut(store, &mut node);
+                    left_bottom = parts.left.take();
+                    right_bottom = parts.right.take();
+                    Self::update(store, &mut node);
+                    detached = Some(node);
+                    break;
+                }
+            }
+        }
+        let left = left_spine
+            .into_iter()
+            .rev()
+            .fold(left_bottom, |acc, mut node| {
+                S::get_mut(store, &mut node).right = acc;
+                Self::update(store, &mut node);
+                Some(node)
+            });
+        let right = right_spine
+            .into_iter()
+            .rev()
+            .fold(right_bottom, |acc, mut node| {
+                S::get_mut(store, &mut node).left = acc;
+                Self::update(store, &mut node);
+                Some(node)
+            });
+        (left, detached, right)
+    }
+
+    /// Splits into the keys for which `pred` holds and the rest; `pred` must
+    /// hold for a prefix of the keys.
+    fn split<F>(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        root: Link<K, P, V, M, U, KC, PC, S>,
+        pred: F,
+    ) -> TwoWay<K, P, V, M, U, KC, PC, S>
+    where
+        F: Fn(&K) -> bool,
+    {
+        let (left, _, right) = Self::split_with(store, root, |_, node| Some(pred(&node.key)));
+        (left, right)
+    }
+
+    /// Splits into the keys below `key`, the detached node holding `key` if
+    /// any, and the keys above it.
+    fn split_by_key<Q>(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        root: Link<K, P, V, M, U, KC, PC, S>,
+        key: &Q,
+    ) -> ThreeWay<K, P, V, M, U, KC, PC, S>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        Self::split_with(store, root, |_, node| Self::side_of(&node.key, key))
+    }
+
+    /// Where `split_by_key` sends the node holding `node_key`.
+    fn side_of<Q>(node_key: &K, key: &Q) -> Option<bool>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        match KC::compare(node_key.borrow(), key) {
+            Ordering::Less => Some(true),
+            Ordering::Equal => None,
+            Ordering::Greater => Some(false),
+        }
+    }
+
+    /// Splits into the first `idx` entries in key order and the rest.
+    fn split_at_index(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        root: Link<K, P, V, M, U, KC, PC, S>,
+        mut idx: usize,
+    ) -> TwoWay<K, P, V, M, U, KC, PC, S> {
+        let (left, _, right) = Self::split_with(store, root, |store, node| {
+            let left_len = Self::len_of(store, &node.left);
+            if left_len < idx {
+                idx -= left_len + 1;
+                Some(true)
+            } else {
+                Some(false)
+            }
+        });
+        (left, right)
+    }
+
+    /// Joins two subtrees where every key of `left` is below every key of
+    /// `right`.
+    ///
+    /// Like `split_with`, this collects the merged spine and links it bottom-up.
+    fn merge(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        left: Link<K, P, V, M, U, KC, PC, S>,
+        right: Link<K, P, V, M, U, KC, PC, S>,
+    ) -> Link<K, P, V, M, U, KC, PC, S> {
+        // each node remembers whether it came from `left` (and awaits its right
+        // child) or from `right` (and awaits its left child)
+        let mut spine = Vec::new();
+        let (mut left, mut right) = (left, right);
+        let bottom = loop {
+            match (left.take(), right.take()) {
+                (None, rest) | (rest, None) => break rest,
+                (Some(mut left_node), Some(mut right_node)) => {
+                    if S::get(store, &left_node).outranks(S::get(store, &right_node)) {
+                        Self::push_down(store, &mut left_node);
+                        left = S::get_mut(store, &mut left_node).right.take();
+                        right = Some(right_node);
+                        spine.push((left_node, true));
+                    } else {
+                        Self::push_down(store, &mut right_node);
+                        right = S::get_mut(store, &mut right_node).left.take();
+                        left = Some(left_node);
+                        spine.push((right_node, false));
+                    }
+                }
+            }
+        };
+        spine
+            .into_iter()
+            .rev()
+            .fold(bottom, |acc, (mut node, from_left)| {
+                let parent = S::get_mut(store, &mut node);
+                if from_left {
+                    parent.right = acc;
+                } else {
+                    parent.left = acc;
+                }
+                Self::update(store, &mut node);
+                Some(node)
+            })
+    }
+
+    pub fn insert(&mut self, k: K, p: P, v: V) -> Option<(K, V)> {
+        let store = &mut self.store;
+        let (left, elem, right) = Self::split_by_key(store, self.root.take(), &k);
+        let new_node = Self::leaf(store, k, p, v);
+        let right = Self::merge(store, Some(new_node), right);
+        self.root = Self::merge(store, left, right);
+        elem.map(|node| Self::release(store, node))
+    }
+
+    pub fn erase<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        let store = &mut self.store;
+        let (left, elem, right) = Self::split_by_key(store, self.root.take(), key);
+        self.root = Self::merge(store, left, right);
+        elem.map(|node| Self::release(store, node))
+    }
+
+    /// Sets the priority of `key` and returns the old one, or `None` if the key
+    /// is absent. O(log n) expected.
+    pub fn update_priority<Q>(&mut self, key: &Q, new_p: P) -> Option<P>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        self.update_priority_with(key, |p| std::mem::replace(p, new_p))
+    }
+
+    /// Calls `f` on the priority of `key` and moves the entry to its new place
+    /// in the heap order. Returns `f`'s result, or `None` if the key is absent.
+    pub fn update_priority_with<Q, F, R>(&mut self, key: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+        F: FnOnce(&mut P) -> R,
+    {
+        let store = &mut self.store;
+        let (left, mut elem, right) = Self::split_by_key(store, self.root.take(), key);
+        let out = elem
+            .as_mut()
+            .map(|node| f(&mut S::get_mut(store, node).priority));
+        let right = Self::merge(store, elem, right);
+        self.root = Self::merge(store, left, right);
+        out
+    }
+
+    /// Merges `other` into `self`. For keys present in both, `resolve` is
+    /// called with the key, the value from `self` and the value from `other`.
+    /// The merged entry keeps the key and priority of the higher-priority
+    /// side, `self` on ties, so its key object may come from `other`.
+    ///
+    /// Join-based: whichever root has the higher priority splits the other
+    /// treap, so this is O(m log(n/m)) expected for sizes m <= n.
+    pub fn union<F>(mut self, mut other: Self, mut resolve: F) -> Self
+    where
+        F: FnMut(&K, V, V) -> V,
+    {
+        let (ours, theirs) = self.adopt(&mut other);
+        self.root = Self::set_op(
+            &mut self.store,
+            ours,
+            theirs,
+            false,
+            |_, ours, theirs| ours.or(theirs),
+            |store, pivot, pivot_ours, dup, left, right| {
+                let pivot = match dup {
+                    None => pivot,
+                    Some(dup) => {
+                        let dup = S::free(store, dup);
+                        S::map(store, pivot, |mut node| {
+                            node.value = if pivot_ours {
+                                resolve(&node.key, node.value, dup.value)
+                            } else {
+                                resolve(&node.key, dup.value, node.value)
+                            };
+                            node
+                        })
+                    }
+                };
+                Self::link(store, pivot, left, right)
+            },
+        );
+        self
+    }
+
+    /// Keeps the entries of `self` whose keys are also in `other`.
+    pub fn intersection(mut self, mut other: Self) -> Self {
+        let (ours, theirs) = self.adopt(&mut other);
+        self.root = Self::set_op(
+            &mut self.store,
+            ours,
+            theirs,
+            false,
+            |store, ours, theirs| {
+                Self::discard(store, ours);
+                Self::discard(store, theirs);
+                None
+            },
+            |store, pivot, pivot_ours, dup, left, right| match dup {
+                None => {
+                    Self::discard(store, Some(pivot));
+                    Self::merge(store, left, right)
+                }
+                Some(dup) if pivot_ours => {
+                    Self::discard(store, Some(dup));
+                    Self::link(store, pivot, left, right)
+                }
+                Some(dup) => {
+                    // our entry keeps its own priority, so it may sink below
+                    // the place of theirs
+                    Self::discard(store, Some(pivot));
+                    let left = Self::merge(store, left, Some(dup));
+                    Self::merge(store, left, right)
+                }
+            },
+        );
+        self
+    }
+
+    /// Keeps the entries of `self` whose keys are not in `other`.
+    pub fn difference(mut self, mut other: Self) -> Self {
+        let (ours, theirs) = self.adopt(&mut other);
+        self.root = Self::set_op(
+            &mut self.store,
+            ours,
+            theirs,
+            true,
+            |store, ours, theirs| {
+                Self::discard(store, theirs);
+                ours
+            },
+            |store, pivot, _, dup, left, right| match dup {
+                Some(dup) => {
+                    Self::discard(store, Some(pivot));
+                    Self::discard(store, Some(dup));
+                    Self::merge(store, left, right)
+                }
+                None => Self::link(store, pivot, left, right),
+            },
+        );
+        self
+    }
+
+    /// Keeps the entries whose keys are in exactly one of `self` and `other`.
+    pub fn symmetric_difference(mut self, mut other: Self) -> Self {
+        let (ours, theirs) = self.adopt(&mut other);
+        self.root = Self::set_op(
+            &mut self.store,
+            ours,
+            theirs,
+            false,
+            |_, ours, theirs| ours.or(theirs),
+            |store, pivot, _, dup, left, right| match dup {
+                Some(dup) => {
+                    Self::discard(store, Some(pivot));
+                    Self::discard(store, Some(dup));
+                    Self::merge(store, left, right)
+                }
+                None => Self::link(store, pivot, left, right),
+            },
+        );
+        self
+    }
+
+    /// Drives the set operations with explicit stacks, since the recursion
+    /// follows the tree depth.
+    ///
+    /// The higher-priority root (always ours if `ours_pivot`) becomes the
+    /// pivot and splits the other treap by its key. `base` handles a pair
+    /// where either side is empty; `join` gets the pivot, whether it came
+    /// from `ours`, the other side's entry with the same key, and the
+    /// combined left and right parts. Both must free the nodes they drop.
+    fn set_op<B, J>(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        ours: Link<K, P, V, M, U, KC, PC, S>,
+        theirs: Link<K, P, V, M, U, KC, PC, S>,
+        ours_pivot: bool,
+        mut base: B,
+        mut join: J,
+    ) -> Link<K, P, V, M, U, KC, PC, S>
+    where
+        B: FnMut(
+            &mut Store<K, P, V, M, U, KC, PC, S>,
+            Link<K, P, V, M, U, KC, PC, S>,
+            Link<K, P, V, M, U, KC, PC, S>,
+        ) -> Link<K, P, V, M, U, KC, PC, S>,
+        J: FnMut(
+            &mut Store<K, P, V, M, U, KC, PC, S>,
+            Handle<K, P, V, M, U, KC, PC, S>,
+            bool,
+            Link<K, P, V, M, U, KC, PC, S>,
+            Link<K, P, V, M, U, KC, PC, S>,
+            Link<K, P, V, M, U, KC, PC, S>,
+        ) -> Link<K, P, V, M, U, KC, PC, S>,
+    {
+        let mut tasks = vec![SetTask::<K, P, V, M, U, KC, PC, S>::Solve(ours, theirs)];
+        let mut done = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                SetTask::Solve(Some(mut ours), Some(theirs))
+                    if ours_pivot || S::get(store, &ours).outranks(S::get(store, &theirs)) =>
+                {
+                    Self::push_down(store, &mut ours);
+                    let (left, dup, right) =
+                        Self::split_with(store, Some(theirs), |store, node| {
+                            Self::side_of(&node.key, &S::get(store, &ours).key)
+                        });
+                    let pivot = S::get_mut(store, &mut ours);
+                    let (ours_l, ours_r) = (pivot.left.take(), pivot.right.take());
+                    tasks.push(SetTask::Join(ours, true, dup));
+                    tasks.push(SetTask::Solve(ours_r, right));
+                    tasks.push(SetTask::Solve(ours_l, left));
+                }
+                SetTask::Solve(Some(ours), Some(mut theirs)) => {
+                    Self::push_down(store, &mut theirs);
+                    let (left, dup, right) = Self::split_with(store, Some(ours), |store, node| {
+                        Self::side_of(&node.key, &S::get(store, &theirs).key)
+                    });
+                    let pivot = S::get_mut(store, &mut theirs);
+                    let (theirs_l, theirs_r) = (pivot.left.take(), pivot.right.take());
+                    tasks.push(SetTask::Join(theirs, false, dup));
+                    tasks.push(SetTask::Solve(right, theirs_r));
+                    tasks.push(SetTask::Solve(left, theirs_l));
+                }
+                SetTask::Solve(ours, theirs) => done.push(base(store, ours, theirs)),
+                SetTask::Join(pivot, pivot_ours, dup) => {
+                    let right = done.pop().unwrap();
+                    let left = done.pop().unwrap();
+                    done.push(join(store, pivot, pivot_ours, dup, left, right));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+
+    fn link(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        mut node: Handle<K, P, V, M, U, KC, PC, S>,
+        left: Link<K, P, V, M, U, KC, PC, S>,
+        right: Link<K, P, V, M, U, KC, PC, S>,
+    ) -> Link<K, P, V, M, U, KC, PC, S> {
+        let parent = S::get_mut(store, &mut node);
+        parent.left = left;
+        parent.right = right;
+        Self::update(store, &mut node);
+        Some(node)
+    }
+
+    /// Takes the roots of both treaps, with `other`'s nodes moved into
+    /// `self`'s store if each treap has its own. The smaller treap's nodes are
+    /// the ones moved, so `self` may take over `other`'s store.
+    fn adopt(&mut self, other: &mut Self) -> TwoWay<K, P, V, M, U, KC, PC, S> {
+        let (mut ours, mut theirs) = (self.root.take(), other.root.take());
+        if S::STORE_OWNS_NODES {
+            if self.len_in(&ours) < other.len_in(&theirs) {
+                std::mem::swap(&mut self.store, &mut other.store);
+                ours = Self::transplant(&mut other.store, &mut self.store, ours);
+            } else {
+                theirs = Self::transplant(&mut other.store, &mut self.store, theirs);
+            }
+            other.store = Default::default();
+        }
+        (ours, theirs)
+    }
+
+    /// Makes a treap of `part`, which was cut from this one. If each treap has
+    /// its own store, the smaller of `part` and the rest moves to a new one.
+    fn detach(&mut self, mut part: Link<K, P, V, M, U, KC, PC, S>) -> Self {
+        let mut other = Self::empty();
+        if S::STORE_OWNS_NODES && self.len_in(&part) > self.len() {
+            std::mem::swap(&mut self.store, &mut other.store);
+            self.root = Self::transplant(&mut other.store, &mut self.store, self.root.take());
+        } else {
+            part = Self::transplant(&mut self.store, &mut other.store, part);
+        }
+        other.root = part;
+        other
+    }
+
+    /// Moves the subtree at `link` from one store to another, rebuilding it
+    /// like `from_sorted_iter`. Handles are the nodes themselves unless the
+    /// store owns them, so then there is nothing to move.
+    fn transplant(
+        from: &mut Store<K, P, V, M, U, KC, PC, S>,
+        to: &mut Store<K, P, V, M, U, KC, PC, S>,
+        link: Link<K, P, V, M, U, KC, PC, S>,
+    ) -> Link<K, P, V, M, U, KC, PC, S> {
+        if !S::STORE_OWNS_NODES {
+            return link;
+        }
+        let mut spine = Vec::new();
+        for node in Self::into_nodes(from, link) {
+            let node = S::alloc(to, S::free(from, node));
+            Self::push_spine(to, &mut spine, node);
+        }
+        Self::pop_spine(to, &mut spine, |_, _| false)
+    }
+
+    /// Moves all entries with keys `>= key` into a new treap.
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        let (left, right) = Self::split(&mut self.store, self.root.take(), |k| {
+            KC::compare(k.borrow(), key).is_lt()
+        });
+        self.root = left;
+        self.detach(right)
+    }
+
+    /// Moves all entries of `other` to the end of `self` in O(log n).
+    ///
+    /// Fails and leaves both treaps untouched unless every key of `other` is
+    /// greater than every key of `self`.
+    pub fn append(&mut self, other: &mut Self) -> Result<(), TreapError> {
+        if let (Some(last), Some(first)) = (self.last_key(), other.first_key()) {
+            if KC::compare(last, first).is_ge() {
+                return Err(TreapError::OverlappingKeys);
+            }
+        }
+        self.concat(other);
+        Ok(())
+    }
+
+    /// Moves all entries with keys in `range` into a new treap.
+    pub fn split_range<Q, R>(&mut self, range: R) -> Self
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+        R: RangeBounds<Q>,
+    {
+        let (lo, hi) = (range.start_bound(), range.end_bound());
+        let store = &mut self.store;
+        let (left, rest) =
+            Self::split(store, self.root.take(), |k| !after_start::<KC, _, _>(k, lo));
+        let (mid, right) = Self::split(store, rest, |k| before_end::<KC, _, _>(k, hi));
+        self.root = Self::merge(store, left, right);
+        self.detach(mid)
+    }
+
+    /// Removes the highest-priority entry whose key lies in `range`, in
+    /// O(log n) expected.
+    pub fn pop_in_range<Q, R>(&mut self, range: R) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+        R: RangeBounds<Q>,
+    {
+        let (lo, hi) = (range.start_bound(), range.end_bound());
+        let store = &mut self.store;
+        let (left, rest) =
+            Self::split(store, self.root.take(), |k| !after_start::<KC, _, _>(k, lo));
+        let (mut mid, right) = Self::split(store, rest, |k| before_end::<KC, _, _>(k, hi));
+        let popped = Self::pop_root(store, &mut mid);
+        let left = Self::merge(store, left, mid);
+        self.root = Self::merge(store, left, right);
+        popped
+    }
+
+    fn first_key(&self) -> Option<&K> {
+        let mut node = S::node(&self.store, &self.root)?;
+        while let Some(left) = S::node(&self.store, &node.left) {
+            node = left;
+        }
+        Some(&node.key)
+    }
+
+    fn last_key(&self) -> Option<&K> {
+        let mut node = S::node(&self.store, &self.root)?;
+        while let Some(right) = S::node(&self.store, &node.right) {
+            node = right;
+        }
+        Some(&node.key)
+    }
+
+    /// Value of `key`, missing any pending updates above it.
+    fn find<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        let mut curr = &self.root;
+        while let Some(node) = S::node(&self.store, curr) {
+            match KC::compare(node.key.borrow(), key) {
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Less => curr = &node.right,
+                Ordering::Greater => curr = &node.left,
+            }
+        }
+        None
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        self.find(key).is_some()
+    }
+
+    /// Like `get`, but pushes pending range updates down the search path
+    /// first, so it also works on treaps with a lazy update type.
+    pub fn lookup<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        S::descend_mut(&mut self.store, &mut self.root, |store, node| {
+            Self::push_down(store, node);
+            KC::compare(key, S::get(store, node).key.borrow())
+        })
+        .map(|node| &node.value)
+    }
+
+    /// Combines the summaries of all values whose keys lie in `range`.
+    pub fn aggregate<Q, R>(&self, range: R) -> M
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+        R: RangeBounds<Q>,
+    {
+        self.aggregate_bounded(range.start_bound(), range.end_bound())
+            .0
+    }
+
+    /// Returns the summary and the number of values it covers.
+    ///
+    /// Descends to the highest node in the range, then sums each side of it
+    /// along the path to the matching bound. Both are loops, so a degenerate
+    /// tree can't overflow the stack.
+    fn aggregate_bounded<Q>(&self, lo: Bound<&Q>, hi: Bound<&Q>) -> (M, usize)
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        let store = &self.store;
+        // nodes above the range, whose pending updates still apply to it
+        let mut path = Vec::new();
+        let mut curr = &self.root;
+        let total = loop {
+            let node = match S::node(store, curr) {
+                None => break (M::identity(), 0),
+                Some(node) => node,
+            };
+            if !after_start::<KC, _, _>(&node.key, lo) {
+                curr = &node.right;
+            } else if !before_end::<KC, _, _>(&node.key, hi) {
+                curr = &node.left;
+            } else {
+                let (left, left_count) = node.pending(Self::aggregate_from(store, &node.left, lo));
+                let (right, right_count) = node.pending(Self::aggregate_to(store, &node.right, hi));
+                let summary = left.combine(&M::from_value(&node.value)).combine(&right);
+                break (summary, left_count + 1 + right_count);
+            }
+            path.push(node);
+        };
+        path.into_iter()
+            .rev()
+            .fold(total, |acc, node| node.pending(acc))
+    }
+
+    /// Summary of the keys in the subtree at `link` satisfying `lo`.
+    fn aggregate_from<Q>(
+        store: &Store<K, P, V, M, U, KC, PC, S>,
+        link: &Link<K, P, V, M, U, KC, PC, S>,
+        lo: Bound<&Q>,
+    ) -> (M, usize)
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        if let Bound::Unbounded = lo {
+            return (Self::summary_of(store, link), Self::len_of(store, link));
+        }
+        // each node on the path, and whether it and its right subtree count
+        let mut path = Vec::new();
+        let mut curr = link;
+        while let Some(node) = S::node(store, curr) {
+            let inside = after_start::<KC, _, _>(&node.key, lo);
+            path.push((node, inside));
+            curr = if inside { &node.left } else { &node.right };
+        }
+        path.into_iter()
+            .rev()
+            .fold((M::identity(), 0), |acc, (node, inside)| {
+                let (summary, count) = node.pending(acc);
+                if !inside {
+                    return (summary, count);
+                }
+                let (right, right_count) = node.pending((
+                    Self::summary_of(store, &node.right),
+                    Self::len_of(store, &node.right),
+                ));
+                let summary = summary.combine(&M::from_value(&node.value)).combine(&right);
+                (summary, count + 1 + right_count)
+            })
+    }
+
+    /// Summary of the keys in the subtree at `link` satisfying `hi`.
+    fn aggregate_to<Q>(
+        store: &Store<K, P, V, M, U, KC, PC, S>,
+        link: &Link<K, P, V, M, U, KC, PC, S>,
+        hi: Bound<&Q>,
+    ) -> (M, usize)
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        if let Bound::Unbounded = hi {
+            return (Self::summary_of(store, link), Self::len_of(store, link));
+        }
+        // each node on the path, and whether it and its left subtree count
+        let mut path = Vec::new();
+        let mut curr = link;
+        while let Some(node) = S::node(store, curr) {
+            let inside = before_end::<KC, _, _>(&node.key, hi);
+            path.push((node, inside));
+            curr = if inside { &node.right } else { &node.left };
+        }
+        path.into_iter()
+            .rev()
+            .fold((M::identity(), 0), |acc, (node, inside)| {
+                let (summary, count) = node.pending(acc);
+                if !inside {
+                    return (summary, count);
+                }
+                let (left, left_count) = node.pending((
+                    Self::summary_of(store, &node.left),
+                    Self::len_of(store, &node.left),
+                ));
+                let summary = left.combine(&M::from_value(&node.value)).combine(&summary);
+                (summary, left_count + 1 + count)
+            })
+    }
+
+    /// Applies `tag` to every value whose key lies in `range`.
+    ///
+    /// The update is pushed down lazily, so this is O(log n) regardless of
+    /// how many entries are in the range.
+    pub fn update_range<Q, R>(&mut self, range: R, tag: U)
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+        R: RangeBounds<Q>,
+    {
+        let (lo, hi) = (range.start_bound(), range.end_bound());
+        let store = &mut self.store;
+        let (left, rest) =
+            Self::split(store, self.root.take(), |k| !after_start::<KC, _, _>(k, lo));
+        let (mut mid, right) = Self::split(store, rest, |k| before_end::<KC, _, _>(k, hi));
+        Self::apply(store, &mut mid, &tag);
+        let right = Self::merge(store, mid, right);
+        self.root = Self::merge(store, left, right);
+    }
+
+    /// Applies `tag` to the entries at positions `range` in key order.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn update_index_range<R: RangeBounds<usize>>(&mut self, range: R, tag: U) {
+        self.apply_index_range(range, tag, false);
+    }
+
+    /// Mirrors the order of the entries at positions `range` and applies
+    /// `tag`, which must mirror their summary to match. Only for keyless
+    /// treaps, since keys would end up out of order.
+    pub(crate) fn reverse_index_range<R: RangeBounds<usize>>(&mut self, range: R, tag: U) {
+        self.apply_index_range(range, tag, true);
+    }
+
+    fn apply_index_range<R: RangeBounds<usize>>(&mut self, range: R, tag: U, reverse: bool) {
+        let (start, end) = index_bounds(&range, self.len());
+        let store = &mut self.store;
+        let (rest, right) = Self::split_at_index(store, self.root.take(), end);
+        let (left, mut mid) = Self::split_at_index(store, rest, start);
+        Self::apply(store, &mut mid, &tag);
+        if let (true, Some(node)) = (reverse, &mut mid) {
+            S::get_mut(store, node).mirror();
+        }
+        let right = Self::merge(store, mid, right);
+        self.root = Self::merge(store, left, right);
+    }
+
+    /// Returns the number of keys strictly smaller than `key`.
+    ///
+    /// If `key` is present this is its position in the sorted order.
+    pub fn rank<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        let mut rank = 0;
+        let mut curr = &self.root;
+        while let Some(node) = S::node(&self.store, curr) {
+            match KC::compare(node.key.borrow(), key) {
+                Ordering::Less => {
+                    rank += self.len_in(&node.left) + 1;
+                    curr = &node.right;
+                }
+                Ordering::Equal => return rank + self.len_in(&node.left),
+                Ordering::Greater => curr = &node.left,
+            }
+        }
+        rank
+    }
+
+    pub fn peek(&self) -> Option<(&K, &V)> {
+        S::node(&self.store, &self.root).map(|node| (&node.key, &node.value))
+    }
+
+    pub fn pop(&mut self) -> Option<(K, V)> {
+        Self::pop_root(&mut self.store, &mut self.root)
+    }
+
+    /// Removes the root of the subtree at `link`, which is its
+    /// highest-priority entry.
+    fn pop_root(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        link: &mut Link<K, P, V, M, U, KC, PC, S>,
+    ) -> Option<(K, V)> {
+        let mut node = link.take()?;
+        Self::push_down(store, &mut node);
+        let parts = S::get_mut(store, &mut node);
+        let (left, right) = (parts.left.take(), parts.right.take());
+        *link = Self::merge(store, left, right);
+        Some(Self::release(store, node))
+    }
+
+    /// Removes the entry with the smallest key. Unlike `pop`, this goes by key
+    /// order, not priority.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        (!self.is_empty()).then(|| self.remove_at_index(0))
+    }
+
+    /// Removes the entry with the largest key.
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        let len = self.len().checked_sub(1)?;
+        Some(self.remove_at_index(len))
+    }
+
+    /// Keeps only the entries for which `f` returns true, in one O(n) pass.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.extract_if(|k, v| !f(k, v)).for_each(drop);
+    }
+
+    /// Removes and yields the entries for which `pred` returns true, in key
+    /// order.
+    ///
+    /// The kept entries are relinked with the stack-based construction of
+    /// `from_sorted_iter`, so a full pass is O(n) and keeps the original
+    /// priorities. Entries not reached before the iterator is dropped are kept.
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, K, P, V, M, U, F, KC, PC, S>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let mut nodes = Self::into_nodes(&mut self.store, self.root.take());
+        nodes.reverse();
+        ExtractIf {
+            treap: self,
+            nodes,
+            kept: Vec::new(),
+            pred,
+        }
+    }
+
+    /// Splits out the entries with keys in `range` in O(log n) and returns
+    /// them as an owning iterator in key order.
+    pub fn drain<Q, R>(&mut self, range: R) -> Drain<K, P, V, M, U, KC, PC, S>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+        R: RangeBounds<Q>,
+    {
+        Drain {
+            inner: IntoIter::new(self.split_range(range)),
+        }
+    }
+
+    /// Detaches all nodes of the subtree at `link` in key order, with pending
+    /// updates pushed down.
+    fn into_nodes(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        link: Link<K, P, V, M, U, KC, PC, S>,
+    ) -> Handles<K, P, V, M, U, KC, PC, S> {
+        let mut nodes = Vec::with_capacity(Self::len_of(store, &link));
+        let mut stack = Vec::new();
+        let mut curr = link;
+        loop {
+            while let Some(mut node) = curr {
+                Self::push_down(store, &mut node);
+                curr = S::get_mut(store, &mut node).left.take();
+                stack.push(node);
+            }
+            match stack.pop() {
+                None => return nodes,
+                Some(mut node) => {
+                    curr = S::get_mut(store, &mut node).right.take();
+                    nodes.push(node);
+                }
+            }
+        }
+    }
+
+    pub fn into_iter_by_priority(self) -> IterByPriority<K, P, V, M, U, KC, PC, S> {
+        IterByPriority { treap: self }
+    }
+
+    pub fn into_vec(self) -> Vec<(K, V)> {
+        self.into_iter().collect()
+    }
+
+    /// Inserts an entry at position `idx` in key order, which the caller has
+    /// checked to fit `key`.
+    pub(crate) fn insert_at_index(&mut self, idx: usize, key: K, priority: P, value: V) {
+        let store = &mut self.store;
+        let (left, right) = Self::split_at_index(store, self.root.take(), idx);
+        let new_node = Self::leaf(store, key, priority, value);
+        let right = Self::merge(store, Some(new_node), right);
+        self.root = Self::merge(store, left, right);
+    }
+
+    /// Removes the entry at position `idx`, which must exist.
+    pub(crate) fn remove_at_index(&mut self, idx: usize) -> (K, V) {
+        let store = &mut self.store;
+        let (left, rest) = Self::split_at_index(store, self.root.take(), idx);
+        let (mid, right) = Self::split_at_index(store, rest, 1);
+        self.root = Self::merge(store, left, right);
+        Self::release(store, mid.expect("index out of bounds"))
+    }
+
+    /// Moves the entries from position `idx` on into a new treap.
+    pub(crate) fn split_off_at_index(&mut self, idx: usize) -> Self {
+        let (left, right) = Self::split_at_index(&mut self.store, self.root.take(), idx);
+        self.root = left;
+        self.detach(right)
+    }
+
+    /// Moves all entries of `other` after those of `self`, whatever their keys.
+    pub(crate) fn concat(&mut self, other: &mut Self) {
+        let (ours, theirs) = self.adopt(other);
+        self.root = Self::merge(&mut self.store, ours, theirs);
+    }
+}
+
+/// Size queries, which need neither comparator.
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC, PC, S: Storage>
+    TreapNodePtr<K, P, V, M, U, KC, PC, S>
+{
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len_in(&self.root)
+    }
+
+    /// Drops every entry.
+    pub fn clear(&mut self) {
+        *self = Self::empty();
+    }
+}
 
-struct TreapNodePtr<K: Ord, P: Ord, V>(Option<Box<TreapNode<K, P, V>>>);
+/// Node handling and the augmentation, which need no comparator either.
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC, PC, S: Storage>
+    TreapNodePtr<K, P, V, M, U, KC, PC, S>
+{
+    fn empty() -> Self {
+        TreapNodePtr {
+            root: None,
+            store: Default::default(),
+        }
+    }
+
+    fn leaf(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        key: K,
+        priority: P,
+        value: V,
+    ) -> Handle<K, P, V, M, U, KC, PC, S> {
+        let summary = M::from_value(&value);
+        S::alloc(
+            store,
+            TreapNode {
+                key,
+                priority,
+                value,
+                size: 1,
+                summary,
+                lazy: None,
+                reversed: false,
+                left: None,
+                right: None,
+                order: PhantomData,
+            },
+        )
+    }
+
+    /// Takes the entry out of a node that no longer has children.
+    fn release(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        node: Handle<K, P, V, M, U, KC, PC, S>,
+    ) -> (K, V) {
+        let node = S::free(store, node);
+        (node.key, node.value)
+    }
+
+    /// Frees the subtree at `link` with an explicit stack instead of the
+    /// recursive drop glue, which could overflow on a degenerate tree.
+    fn discard(store: &mut Store<K, P, V, M, U, KC, PC, S>, link: Link<K, P, V, M, U, KC, PC, S>) {
+        let mut stack: Vec<_> = link.into_iter().collect();
+        while let Some(node) = stack.pop() {
+            let node = S::free(store, node);
+            stack.extend(node.left);
+            stack.extend(node.right);
+        }
+    }
+
+    fn len_of(
+        store: &Store<K, P, V, M, U, KC, PC, S>,
+        link: &Link<K, P, V, M, U, KC, PC, S>,
+    ) -> usize {
+        S::node(store, link).map_or(0, |node| node.size)
+    }
+
+    /// Size of a subtree of this treap.
+    fn len_in(&self, link: &Link<K, P, V, M, U, KC, PC, S>) -> usize {
+        Self::len_of(&self.store, link)
+    }
+
+    fn summary_of(
+        store: &Store<K, P, V, M, U, KC, PC, S>,
+        link: &Link<K, P, V, M, U, KC, PC, S>,
+    ) -> M {
+        S::node(store, link).map_or_else(M::identity, |node| node.summary.clone())
+    }
+
+    /// Recomputes the subtree augmentation after the children changed.
+    fn update(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        node: &mut Handle<K, P, V, M, U, KC, PC, S>,
+    ) {
+        let parts = S::get(store, node);
+        let size = 1 + Self::len_of(store, &parts.left) + Self::len_of(store, &parts.right);
+        let summary = Self::summary_of(store, &parts.left)
+            .combine(&M::from_value(&parts.value))
+            .combine(&Self::summary_of(store, &parts.right));
+        let parts = S::get_mut(store, node);
+        parts.size = size;
+        parts.summary = summary;
+    }
+
+    fn apply(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        link: &mut Link<K, P, V, M, U, KC, PC, S>,
+        tag: &U,
+    ) {
+        if let Some(node) = link {
+            S::get_mut(store, node).apply(tag);
+        }
+    }
+
+    /// Must be called before the children are detached or read.
+    fn push_down(
+        store: &mut Store<K, P, V, M, U, KC, PC, S>,
+        node: &mut Handle<K, P, V, M, U, KC, PC, S>,
+    ) {
+        let parts = S::get_mut(store, node);
+        let (tag, reversed) = (parts.lazy.take(), std::mem::take(&mut parts.reversed));
+        if tag.is_some() || reversed {
+            let (mut left, mut right) = (parts.left.take(), parts.right.take());
+            for child in [&mut left, &mut right] {
+                if let Some(tag) = &tag {
+                    Self::apply(store, child, tag);
+                }
+                if let (true, Some(child)) = (reversed, child) {
+                    S::get_mut(store, child).mirror();
+                }
+            }
+            let parts = S::get_mut(store, node);
+            parts.left = left;
+            parts.right = right;
+        }
+    }
+}
+
+/// A store that owns its nodes drops them all at once; otherwise the tree is
+/// torn down with `discard`.
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC, PC, S: Storage> Drop
+    for TreapNodePtr<K, P, V, M, U, KC, PC, S>
+{
+    fn drop(&mut self) {
+        if !S::STORE_OWNS_NODES {
+            Self::discard(&mut self.store, self.root.take());
+        }
+    }
+}
+
+// Reads through `&self`, which need `U = ()`.
+impl<K, P, V, M: Monoid<V>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    TreapNodePtr<K, P, V, M, (), KC, PC, S>
+{
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        self.find(key)
+    }
+
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        self.nth(0)
+    }
+
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        self.nth(self.len().checked_sub(1)?)
+    }
+
+    /// Returns the entry with the largest key `<= key`.
+    pub fn floor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        self.below(key, true)
+    }
+
+    /// Returns the entry with the smallest key `>= key`.
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        self.above(key, true)
+    }
+
+    /// Returns the entry with the largest key `< key`.
+    pub fn predecessor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        self.below(key, false)
+    }
+
+    /// Returns the entry with the smallest key `> key`.
+    pub fn successor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        self.above(key, false)
+    }
+
+    /// Closest entry below `key`, or equal to it if `inclusive`.
+    fn below<Q>(&self, key: &Q, inclusive: bool) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        let mut best = None;
+        let mut curr = &self.root;
+        while let Some(node) = S::node(&self.store, curr) {
+            match KC::compare(node.key.borrow(), key) {
+                Ordering::Equal if inclusive => return Some((&node.key, &node.value)),
+                Ordering::Less => {
+                    best = Some((&node.key, &node.value));
+                    curr = &node.right;
+                }
+                _ => curr = &node.left,
+            }
+        }
+        best
+    }
+
+    /// Closest entry above `key`, or equal to it if `inclusive`.
+    fn above<Q>(&self, key: &Q, inclusive: bool) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        let mut best = None;
+        let mut curr = &self.root;
+        while let Some(node) = S::node(&self.store, curr) {
+            match KC::compare(node.key.borrow(), key) {
+                Ordering::Equal if inclusive => return Some((&node.key, &node.value)),
+                Ordering::Greater => {
+                    best = Some((&node.key, &node.value));
+                    curr = &node.left;
+                }
+                _ => curr = &node.right,
+            }
+        }
+        best
+    }
+
+    /// Returns the entry with the `i`-th smallest key (0-indexed).
+    pub fn nth(&self, mut i: usize) -> Option<(&K, &V)> {
+        let mut curr = &self.root;
+        while let Some(node) = S::node(&self.store, curr) {
+            let left_len = self.len_in(&node.left);
+            match i.cmp(&left_len) {
+                Ordering::Less => curr = &node.left,
+                Ordering::Equal => return Some((&node.key, &node.value)),
+                Ordering::Greater => {
+                    i -= left_len + 1;
+                    curr = &node.right;
+                }
+            }
+        }
+        None
+    }
+
+    /// Iterates over the entries in ascending key order.
+    pub fn iter(&self) -> Iter<'_, K, P, V, M, KC, PC, S> {
+        Iter::new(self)
+    }
+
+    /// Iterates over the entries whose keys lie in `range`, in ascending order.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, P, V, M, KC, PC, S>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+        R: RangeBounds<Q>,
+    {
+        Range {
+            inner: Iter::new_range(self, range.start_bound(), range.end_bound()),
+        }
+    }
+
+    /// Returns the highest-priority entry whose key lies in `range`.
+    ///
+    /// That is the first node on the search path that falls inside the range,
+    /// since every other key in the range lies below it.
+    pub fn peek_in_range<Q, R>(&self, range: R) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+        R: RangeBounds<Q>,
+    {
+        let (lo, hi) = (range.start_bound(), range.end_bound());
+        let mut curr = &self.root;
+        while let Some(node) = S::node(&self.store, curr) {
+            if !after_start::<KC, _, _>(&node.key, lo) {
+                curr = &node.right;
+            } else if !before_end::<KC, _, _>(&node.key, hi) {
+                curr = &node.left;
+            } else {
+                return Some((&node.key, &node.value));
+            }
+        }
+        None
+    }
+
+    /// Iterates over the (at most) `k` highest-priority entries whose keys lie
+    /// in `range`, by decreasing priority.
+    pub fn top_k_in_range<Q, R>(&self, range: R, k: usize) -> TopKInRange<'_, K, P, V, M, KC, PC, S>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+        R: RangeBounds<Q>,
+    {
+        TopKInRange::new(self, range.start_bound(), range.end_bound(), k)
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, P, V, M, KC, PC, S> {
+        Keys { inner: self.iter() }
+    }
 
-struct TreapNode<K: Ord, P: Ord, V> {
-    key: K,
-    priority: P,
-    value: V,
-    left: TreapNodePtr<K, P, V>,
-    right: TreapNodePtr<K, P, V>,
+    pub fn values(&self) -> Values<'_, K, P, V, M, KC, PC, S> {
+        Values { inner: self.iter() }
+    }
 }
 
-type Treap<K, P, V> = TreapNodePtr<K, P, V>;
+// In-place mutation, which needs `M = ()` and `U = ()`.
+impl<K, P, V, KC: Compare<K>, PC: Compare<P>, S: Storage> TreapNodePtr<K, P, V, (), (), KC, PC, S> {
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        S::descend_mut(&mut self.store, &mut self.root, |store, node| {
+            KC::compare(key, S::get(store, node).key.borrow())
+        })
+        .map(|node| &mut node.value)
+    }
+
+    /// Iterates over the entries in ascending key order, with mutable values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, P, V, KC, PC, S> {
+        IterMut::new(self)
+    }
+
+    pub fn range_mut<Q, R>(&mut self, range: R) -> RangeMut<'_, K, P, V, KC, PC, S>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+        R: RangeBounds<Q>,
+    {
+        RangeMut {
+            inner: IterMut::new_range(self, range.start_bound(), range.end_bound()),
+        }
+    }
 
-impl<K: Ord, P: Ord, V> From<Box<TreapNode<K, P, V>>> for TreapNodePtr<K, P, V> {
-    fn from(node: Box<TreapNode<K, P, V>>) -> Self {
-        TreapNodePtr(Some(node))
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, P, V, KC, PC, S> {
+        ValuesMut {
+            inner: self.iter_mut(),
+        }
     }
 }
 
-impl<K: Ord, P: Ord, V> TreapNodePtr<K, P, V> {
-    pub fn default() -> Self {
-        TreapNodePtr(None)
+/// Resolves `range` to `[start, end)` within `0..len`, panicking like slice indexing.
+pub(crate) fn index_bounds<R: RangeBounds<usize>>(range: &R, len: usize) -> (usize, usize) {
+    let start = match range.start_bound() {
+        Bound::Included(s) => *s,
+        Bound::Excluded(s) => s
+            .checked_add(1)
+            .expect("attempted to index from after maximum usize"),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(e) => e
+            .checked_add(1)
+            .expect("attempted to index up to maximum usize"),
+        Bound::Excluded(e) => *e,
+        Bound::Unbounded => len,
+    };
+    if start > end {
+        panic!("range starts at {start} but ends at {end}");
+    }
+    if end > len {
+        panic!("range end index {end} out of range for length {len}");
     }
+    (start, end)
+}
 
-    fn new(key: K, priority: P, value: V) -> Self {
-        TreapNodePtr(Some(Box::from(TreapNode {
-            key,
-            priority,
-            value,
-            left: TreapNodePtr(None),
-            right: TreapNodePtr(None),
-        })))
+pub(crate) fn after_start<C, K, Q>(key: &K, start: Bound<&Q>) -> bool
+where
+    K: Borrow<Q>,
+    Q: ?Sized,
+    C: Compare<Q>,
+{
+    match start {
+        Bound::Included(s) => C::compare(key.borrow(), s).is_ge(),
+        Bound::Excluded(s) => C::compare(key.borrow(), s).is_gt(),
+        Bound::Unbounded => true,
     }
+}
 
-    fn take(&mut self) -> Self {
-        TreapNodePtr(self.0.take())
+pub(crate) fn before_end<C, K, Q>(key: &K, end: Bound<&Q>) -> bool
+where
+    K: Borrow<Q>,
+    Q: ?Sized,
+    C: Compare<Q>,
+{
+    match end {
+        Bound::Included(e) => C::compare(key.borrow(), e).is_le(),
+        Bound::Excluded(e) => C::compare(key.borrow(), e).is_lt(),
+        Bound::Unbounded => true,
     }
+}
 
-    fn split<F>(self, pred: F) -> (Self, Self)
-    where
-        F: Fn(&K) -> bool,
-    {
-        if let Some(mut node) = self.0 {
-            let left = node.left.take();
-            let right = node.right.take();
-            if pred(&node.key) {
-                let (right_l, right_r) = right.split(pred);
-                node.left = left;
-                node.right = right_l;
-                (TreapNodePtr(Some(node)), right_r)
-            } else {
-                let (left_l, left_r) = left.split(pred);
-                node.left = left_r;
-                node.right = right;
-                (left_l, TreapNodePtr(Some(node)))
-            }
-        } else {
-            (TreapNodePtr(None), TreapNodePtr(None))
-        }
+pub struct IterByPriority<
+    K,
+    P,
+    V,
+    M: Monoid<V>,
+    U: Update<V, M>,
+    KC = Natural,
+    PC = Natural,
+    S: Storage = Boxed,
+> {
+    treap: TreapNodePtr<K, P, V, M, U, KC, PC, S>,
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage> Iterator
+    for IterByPriority<K, P, V, M, U, KC, PC, S>
+{
+    type Item = (K, V);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.treap.pop()
     }
+}
 
-    fn split_by_key(self, key: &K) -> (Self, Option<(K, V)>, Self) {
-        if let Some(mut node) = self.0 {
-            let left = node.left.take();
-            let right = node.right.take();
-            if node.key == *key {
-                (left, Some((node.key, node.value)), right)
-            } else if node.key < *key {
-                let (right_l, elem, right_r) = right.split_by_key(key);
-                node.left = left;
-                node.right = right_l;
-                (TreapNodePtr(Some(node)), elem, right_r)
-            } else {
-                let (left_l, elem, left_r) = left.split_by_key(key);
-                node.left = left_r;
-                node.right = right;
-                (left_l, elem, TreapNodePtr(Some(node)))
+/// Iterator removing the entries that match a predicate, created by
+/// `Treap::extract_if`.
+pub struct ExtractIf<'a, K, P, V, M, U, F, KC = Natural, PC = Natural, S = Boxed>
+where
+    M: Monoid<V>,
+    U: Update<V, M>,
+    KC: Compare<K>,
+    PC: Compare<P>,
+    S: Storage,
+{
+    treap: &'a mut TreapNodePtr<K, P, V, M, U, KC, PC, S>,
+    /// Nodes still to be checked, the next one last.
+    nodes: Handles<K, P, V, M, U, KC, PC, S>,
+    /// Right spine of the treap of kept entries built so far.
+    kept: Handles<K, P, V, M, U, KC, PC, S>,
+    pred: F,
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, F, KC: Compare<K>, PC: Compare<P>, S: Storage> Iterator
+    for ExtractIf<'_, K, P, V, M, U, F, KC, PC, S>
+where
+    F: FnMut(&K, &mut V) -> bool,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let store = &mut self.treap.store;
+        while let Some(mut node) = self.nodes.pop() {
+            let entry = S::get_mut(store, &mut node);
+            if (self.pred)(&entry.key, &mut entry.value) {
+                return Some(TreapNodePtr::<K, P, V, M, U, KC, PC, S>::release(
+                    store, node,
+                ));
             }
-        } else {
-            (TreapNodePtr(None), None, TreapNodePtr(None))
+            TreapNodePtr::<K, P, V, M, U, KC, PC, S>::push_spine(store, &mut self.kept, node);
         }
+        None
     }
 
-    fn merge(left: Self, right: Self) -> Self {
-        let mut left_node: Box<TreapNode<K, P, V>>;
-        let mut right_node: Box<TreapNode<K, P, V>>;
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.nodes.len()))
+    }
+}
 
-        match left.0 {
-            None => return right,
-            Some(node) => left_node = node,
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, F, KC: Compare<K>, PC: Compare<P>, S: Storage> Drop
+    for ExtractIf<'_, K, P, V, M, U, F, KC, PC, S>
+{
+    fn drop(&mut self) {
+        let store = &mut self.treap.store;
+        while let Some(node) = self.nodes.pop() {
+            TreapNodePtr::<K, P, V, M, U, KC, PC, S>::push_spine(store, &mut self.kept, node);
         }
+        self.treap.root =
+            TreapNodePtr::<K, P, V, M, U, KC, PC, S>::pop_spine(store, &mut self.kept, |_, _| {
+                false
+            });
+    }
+}
+
+/// Owning in-order iterator over a treap, created by `into_iter`.
+pub struct IntoIter<
+    K,
+    P,
+    V,
+    M: Monoid<V>,
+    U: Update<V, M>,
+    KC = Natural,
+    PC = Natural,
+    S: Storage = Boxed,
+> {
+    /// The emptied treap, which keeps the store of the nodes below.
+    treap: TreapNodePtr<K, P, V, M, U, KC, PC, S>,
+    /// Nodes whose left subtree has been consumed, deepest on top.
+    stack: Handles<K, P, V, M, U, KC, PC, S>,
+    remaining: usize,
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    IntoIter<K, P, V, M, U, KC, PC, S>
+{
+    fn new(mut treap: TreapNodePtr<K, P, V, M, U, KC, PC, S>) -> Self {
+        let root = treap.root.take();
+        let mut iter = IntoIter {
+            remaining: treap.len_in(&root),
+            treap,
+            stack: Vec::new(),
+        };
+        iter.push_left_spine(root);
+        iter
+    }
 
-        match right.0 {
-            None => return TreapNodePtr(Some(left_node)),
-            Some(node) => right_node = node,
+    fn push_left_spine(&mut self, mut curr: Link<K, P, V, M, U, KC, PC, S>) {
+        let store = &mut self.treap.store;
+        while let Some(mut node) = curr {
+            TreapNodePtr::<K, P, V, M, U, KC, PC, S>::push_down(store, &mut node);
+            curr = S::get_mut(store, &mut node).left.take();
+            self.stack.push(node);
         }
+    }
+}
 
-        if left_node.priority >= right_node.priority {
-            let left_r = left_node.right.take();
-            left_node.right = TreapNodePtr::merge(left_r, TreapNodePtr::from(right_node));
-            TreapNodePtr::from(left_node)
-        } else {
-            let right_l = right_node.left.take();
-            right_node.left = TreapNodePtr::merge(TreapNodePtr::from(left_node), right_l);
-            TreapNodePtr::from(right_node)
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage> Iterator
+    for IntoIter<K, P, V, M, U, KC, PC, S>
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut node = self.stack.pop()?;
+        self.remaining -= 1;
+        let right = S::get_mut(&mut self.treap.store, &mut node).right.take();
+        self.push_left_spine(right);
+        Some(TreapNodePtr::<K, P, V, M, U, KC, PC, S>::release(
+            &mut self.treap.store,
+            node,
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// Links the nodes not yet yielded back into the treap, whose drop frees them.
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC, PC, S: Storage> Drop
+    for IntoIter<K, P, V, M, U, KC, PC, S>
+{
+    fn drop(&mut self) {
+        let mut below = None;
+        while let Some(mut node) = self.stack.pop() {
+            S::get_mut(&mut self.treap.store, &mut node).left = below;
+            below = Some(node);
         }
+        self.treap.root = below;
     }
+}
 
-    pub fn insert(&mut self, k: K, p: P, v: V) -> Option<(K, V)> {
-        let root = self.take();
-        let (left, elem, right) = root.split_by_key(&k);
-        let new_node = TreapNodePtr::new(k, p, v);
-        *self = TreapNodePtr::merge(left, TreapNodePtr::merge(new_node, right));
-        elem
-    }
-
-    pub fn erase(&mut self, key: &K) -> Option<(K, V)> {
-        let root = self.take();
-        let (left, elem, right) = root.split_by_key(key);
-        *self = TreapNodePtr::merge(left, right);
-        elem
-    }
-
-    pub fn get(&self, key: &K) -> Option<&V> {
-        self.0
-            .as_ref()
-            .map_or(None, |node| match node.key.cmp(key) {
-                Ordering::Equal => Some(&node.value),
-                Ordering::Less => node.right.get(key),
-                Ordering::Greater => node.left.get(key),
-            })
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    ExactSizeIterator for IntoIter<K, P, V, M, U, KC, PC, S>
+{
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    FusedIterator for IntoIter<K, P, V, M, U, KC, PC, S>
+{
+}
+
+/// Owning in-order iterator over a drained key range, created by `Treap::drain`.
+pub struct Drain<
+    K,
+    P,
+    V,
+    M: Monoid<V>,
+    U: Update<V, M>,
+    KC = Natural,
+    PC = Natural,
+    S: Storage = Boxed,
+> {
+    inner: IntoIter<K, P, V, M, U, KC, PC, S>,
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage> Iterator
+    for Drain<K, P, V, M, U, KC, PC, S>
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
     }
 
-    pub fn contains(&self, key: &K) -> bool {
-        self.get(key).is_some()
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
     }
+}
 
-    pub fn peek(&self) -> Option<(&K, &V)> {
-        self.0.as_ref().map(|node| (&node.key, &node.value))
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    ExactSizeIterator for Drain<K, P, V, M, U, KC, PC, S>
+{
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    FusedIterator for Drain<K, P, V, M, U, KC, PC, S>
+{
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage> Default
+    for TreapNodePtr<K, P, V, M, U, KC, PC, S>
+where
+    KC: Default,
+    PC: Default,
+{
+    fn default() -> Self {
+        Self::empty()
     }
+}
 
-    pub fn pop(&mut self) -> Option<(K, V)> {
-        let root = self.take();
-        match root.0 {
-            None => None,
-            Some(mut node) => {
-                let left = node.left.take();
-                let right = node.right.take();
-                *self = TreapNodePtr::merge(left, right);
-                Some((node.key, node.value))
+/// Only for the plain treap, so that `Treap::new()` needs no annotation.
+impl<K, P, V, S: Storage> TreapNodePtr<K, P, V, (), (), Natural, Natural, S> {
+    pub fn new() -> Self {
+        Self::empty()
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, S: Storage>
+    TreapNodePtr<K, P, V, M, U, Natural, Natural, S>
+{
+    /// Creates an empty treap with summary type `M` and update type `U`.
+    pub fn with_monoid() -> Self {
+        Self::empty()
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    TreapNodePtr<K, P, V, M, U, KC, PC, S>
+{
+    /// Creates an empty treap ordered by `KC` and `PC`, such as a [`MinTreap`].
+    pub fn with_order() -> Self {
+        Self::empty()
+    }
+}
+
+/// Copies the tree as is, pending updates included. Nodes are cloned in
+/// post-order with explicit stacks, so a degenerate tree can't overflow.
+impl<K, P, V, M, U, KC: Compare<K>, PC: Compare<P>, S: Storage> Clone
+    for TreapNodePtr<K, P, V, M, U, KC, PC, S>
+where
+    K: Clone,
+    P: Clone,
+    V: Clone,
+    M: Monoid<V>,
+    U: Update<V, M>,
+{
+    fn clone(&self) -> Self {
+        let mut clone = Self::empty();
+        // (subtree, whether its children have been cloned already)
+        let mut todo = vec![(&self.root, false)];
+        let mut done = Vec::new();
+        while let Some((link, children_done)) = todo.pop() {
+            let node = match S::node(&self.store, link) {
+                None => {
+                    done.push(None);
+                    continue;
+                }
+                Some(node) => node,
+            };
+            if !children_done {
+                todo.push((link, true));
+                todo.push((&node.right, false));
+                todo.push((&node.left, false));
+                continue;
             }
+            let right = done.pop().expect("right child is cloned");
+            let left = done.pop().expect("left child is cloned");
+            done.push(Some(S::alloc(
+                &mut clone.store,
+                TreapNode {
+                    key: node.key.clone(),
+                    priority: node.priority.clone(),
+                    value: node.value.clone(),
+                    size: node.size,
+                    summary: node.summary.clone(),
+                    lazy: node.lazy.clone(),
+                    reversed: node.reversed,
+                    left,
+                    right,
+                    order: PhantomData,
+                },
+            )));
         }
+        clone.root = done.pop().expect("root is cloned");
+        clone
+    }
+}
+
+impl<K: fmt::Debug, P, V: fmt::Debug, M: Monoid<V>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    fmt::Debug for TreapNodePtr<K, P, V, M, (), KC, PC, S>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
     }
+}
 
-    pub fn into_iter_by_priority(self) -> IterByPriority<K, P, V> {
-        IterByPriority { treap: self }
+/// Treaps are equal if they hold the same entries; priorities and shape
+/// don't matter.
+impl<K: PartialEq, P, V: PartialEq, M: Monoid<V>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    PartialEq for TreapNodePtr<K, P, V, M, (), KC, PC, S>
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
     }
+}
+
+impl<K: Eq, P, V: Eq, M: Monoid<V>, KC: Compare<K>, PC: Compare<P>, S: Storage> Eq
+    for TreapNodePtr<K, P, V, M, (), KC, PC, S>
+{
+}
 
-    fn collect(self, vec: &mut Vec<(K, V)>) {
-        if let Some(mut node) = self.0 {
-            let right = node.right.take();
-            node.left.collect(vec);
-            vec.push((node.key, node.value));
-            right.collect(vec)
+impl<K: Hash, P, V: Hash, M: Monoid<V>, KC: Compare<K>, PC: Compare<P>, S: Storage> Hash
+    for TreapNodePtr<K, P, V, M, (), KC, PC, S>
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len());
+        for entry in self.iter() {
+            entry.hash(state);
         }
     }
+}
 
-    pub fn into_vec(self) -> Vec<(K, V)> {
-        let mut vec = Vec::new();
-        self.collect(&mut vec);
-        vec
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    FromIterator<(K, P, V)> for TreapNodePtr<K, P, V, M, U, KC, PC, S>
+{
+    fn from_iter<I: IntoIterator<Item = (K, P, V)>>(iter: I) -> Self {
+        let mut treap = Self::empty();
+        treap.extend(iter);
+        treap
     }
 }
 
-struct IterByPriority<K: Ord, P: Ord, V> {
-    treap: Treap<K, P, V>,
+/// Inserts each entry in turn, so later duplicates replace earlier ones.
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    Extend<(K, P, V)> for TreapNodePtr<K, P, V, M, U, KC, PC, S>
+{
+    fn extend<I: IntoIterator<Item = (K, P, V)>>(&mut self, iter: I) {
+        for (k, p, v) in iter {
+            self.insert(k, p, v);
+        }
+    }
 }
 
-impl<K: Ord, P: Ord, V> Iterator for IterByPriority<K, P, V> {
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    IntoIterator for TreapNodePtr<K, P, V, M, U, KC, PC, S>
+{
     type Item = (K, V);
-    fn next(&mut self) -> Option<Self::Item> {
-        self.treap.pop()
+    type IntoIter = IntoIter<K, P, V, M, U, KC, PC, S>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self)
+    }
+}
+
+impl<'a, K, P, V, M: Monoid<V>, KC: Compare<K>, PC: Compare<P>, S: Storage> IntoIterator
+    for &'a TreapNodePtr<K, P, V, M, (), KC, PC, S>
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, P, V, M, KC, PC, S>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K, P, V, KC: Compare<K>, PC: Compare<P>, S: Storage> IntoIterator
+    for &'a mut TreapNodePtr<K, P, V, (), (), KC, PC, S>
+{
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, P, V, KC, PC, S>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<K, P, V, M, KC: Compare<K>, PC: Compare<P>, S: Storage, Q> Index<&Q>
+    for TreapNodePtr<K, P, V, M, (), KC, PC, S>
+where
+    K: Borrow<Q>,
+    M: Monoid<V>,
+    Q: ?Sized,
+    KC: Compare<Q>,
+{
+    type Output = V;
+
+    /// Panics if the key is not present.
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("key not found in treap")
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::treap::Treap;
+    use crate::compare::Compare;
+    use crate::monoid::{Max, Min, RangeUpdate, Sum};
+    use crate::treap::{Entry, MinTreap, Range, Treap, TreapError};
+    use std::cmp::Ordering;
+    use std::ops::Bound;
     #[test]
     fn it_works() {
         let mut treap: Treap<&str, i64, &str> = Treap::default();
@@ -205,4 +2065,768 @@ mod tests {
         assert_eq!(None, treap.get(&"k2"));
         assert_eq!(Some(&"v3"), treap.get(&"k3"));
     }
+
+    #[test]
+    fn test_order_statistics() {
+        let mut treap: Treap<i32, i32, i32> = Treap::default();
+        let keys = [50, 20, 80, 10, 30, 70, 90, 60, 40];
+        for (i, k) in keys.iter().enumerate() {
+            // pseudo-random priorities to get a non-trivial shape
+            treap.insert(*k, (i as i32 * 7919) % 13, k * 10);
+        }
+        assert_eq!(keys.len(), treap.len());
+
+        for (i, k) in (10..=90).step_by(10).enumerate() {
+            assert_eq!(Some((&k, &(k * 10))), treap.nth(i));
+            assert_eq!(i, treap.rank(&k));
+        }
+        assert_eq!(None, treap.nth(9));
+        assert_eq!(0, treap.rank(&5));
+        assert_eq!(3, treap.rank(&35));
+        assert_eq!(9, treap.rank(&100));
+
+        treap.erase(&30);
+        treap.pop();
+        assert_eq!(7, treap.len());
+        assert_eq!(2, treap.rank(&40));
+        assert_eq!(Some((&40, &400)), treap.nth(2));
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let mut sums: Treap<u32, u32, i64, Sum<i64>> = Treap::with_monoid();
+        let mut maxs: Treap<u32, u32, i64, Max<i64>> = Treap::with_monoid();
+        for t in 0..100u32 {
+            let v = (t as i64 * 37) % 101 - 50;
+            let p = t.wrapping_mul(2654435761);
+            sums.insert(t, p, v);
+            maxs.insert(t, p, v);
+        }
+        let value = |t: u32| (t as i64 * 37) % 101 - 50;
+
+        assert_eq!(Sum((10..20).map(value).sum()), sums.aggregate(10..20));
+        assert_eq!(Sum((10..=20).map(value).sum()), sums.aggregate(10..=20));
+        assert_eq!(Sum((0..100).map(value).sum()), sums.aggregate(..));
+        assert_eq!(Sum(0), sums.aggregate(200..));
+        assert_eq!(Max((37..).take(63).map(value).max()), maxs.aggregate(37..));
+
+        sums.erase(&15);
+        assert_eq!(
+            Sum((10..20).filter(|t| *t != 15).map(value).sum()),
+            sums.aggregate(10..20)
+        );
+
+        let empty: Treap<u32, u32, i64, Min<i64>> = Treap::with_monoid();
+        assert_eq!(Min(None), empty.aggregate(..));
+    }
+
+    #[test]
+    fn test_range_updates() {
+        let mut treap: Treap<u32, u32, i64, Sum<i64>, RangeUpdate<i64>> = Treap::with_monoid();
+        let mut expected = vec![0i64; 50];
+        for t in 0..50u32 {
+            treap.insert(t * 2, t.wrapping_mul(2654435761), 0);
+        }
+
+        treap.update_range(10..=40, RangeUpdate::Add(3));
+        (5..=20).for_each(|i| expected[i] += 3);
+        treap.update_index_range(15..30, RangeUpdate::Assign(-1));
+        (15..30).for_each(|i| expected[i] = -1);
+        treap.update_range(..21, RangeUpdate::Add(2));
+        (0..=10).for_each(|i| expected[i] += 2);
+        treap.update_range(81..85, RangeUpdate::Add(100));
+        (41..=42).for_each(|i| expected[i] += 100);
+
+        // keys are 2 * index
+        assert_eq!(Sum(expected[6..25].iter().sum()), treap.aggregate(12..50));
+        assert_eq!(Sum(expected.iter().sum()), treap.aggregate(..));
+        assert_eq!(Some(&expected[20]), treap.lookup(&40));
+
+        treap.erase(&84);
+        expected.remove(42);
+        treap.insert(85, 7, 5);
+        expected.insert(42, 5);
+        let values: Vec<i64> = treap.into_vec().into_iter().map(|(_, v)| v).collect();
+        assert_eq!(expected, values);
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut treap: Treap<i32, i32, i32> = Treap::default();
+        for k in [5, 3, 8, 1, 4, 7, 9, 2, 6] {
+            treap.insert(k, (k * 7919) % 11, k * 10);
+        }
+
+        let keys: Vec<i32> = treap.keys().copied().collect();
+        assert_eq!((1..=9).collect::<Vec<_>>(), keys);
+        let values: Vec<i32> = treap.values().rev().copied().collect();
+        assert_eq!((1..=9).rev().map(|k| k * 10).collect::<Vec<_>>(), values);
+
+        let mut iter = treap.iter();
+        assert_eq!(9, iter.len());
+        assert_eq!(Some((&1, &10)), iter.next());
+        assert_eq!(Some((&9, &90)), iter.next_back());
+        assert_eq!(Some((&2, &20)), iter.next());
+        assert_eq!(Some((&8, &80)), iter.next_back());
+        assert_eq!(5, iter.len());
+        assert_eq!(
+            vec![3, 4, 5, 6, 7],
+            iter.map(|(k, _)| *k).collect::<Vec<_>>()
+        );
+
+        for (k, v) in treap.iter_mut().rev().take(3) {
+            *v += k;
+        }
+        treap.values_mut().for_each(|v| *v += 1);
+        assert_eq!(Some(&61), treap.get(&6));
+        assert_eq!(Some(&78), treap.get(&7));
+        *treap.get_mut(&4).unwrap() = 0;
+        assert_eq!(Some(&0), treap.get(&4));
+        assert_eq!(None, treap.get_mut(&10));
+    }
+
+    #[test]
+    fn test_range() {
+        let mut treap: Treap<i32, i32, i32> = Treap::default();
+        for k in 0..100 {
+            treap.insert(k * 2, (k * 7919) % 101, k);
+        }
+        let keys = |r: Range<'_, i32, i32, i32, ()>| r.map(|(k, _)| *k).collect::<Vec<_>>();
+
+        assert_eq!(
+            (10..20).step_by(2).collect::<Vec<_>>(),
+            keys(treap.range(10..20))
+        );
+        assert_eq!(
+            (10..=20).step_by(2).collect::<Vec<_>>(),
+            keys(treap.range(9..=20))
+        );
+        assert_eq!(
+            (0..7).step_by(2).collect::<Vec<_>>(),
+            keys(treap.range(..7))
+        );
+        assert_eq!(vec![196, 198], keys(treap.range(195..)));
+        assert_eq!(Vec::<i32>::new(), keys(treap.range(300..)));
+        assert_eq!(100, treap.range(..).len());
+
+        let mut range = treap.range((Bound::Excluded(40), Bound::Excluded(50)));
+        assert_eq!(4, range.len());
+        assert_eq!(Some((&48, &24)), range.next_back());
+        assert_eq!(Some((&42, &21)), range.next());
+
+        for (_, v) in treap.range_mut(100..110) {
+            *v = -1;
+        }
+        assert_eq!(Some(&49), treap.get(&98));
+        assert_eq!(Some(&-1), treap.get(&108));
+        assert_eq!(Some(&55), treap.get(&110));
+        assert_eq!(
+            5,
+            treap
+                .range_mut(100..110)
+                .rev()
+                .filter(|(_, v)| **v == -1)
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut counts: Treap<&str, u32, u32> = Treap::default();
+        for (i, word) in ["b", "a", "c", "a", "b", "a"].iter().enumerate() {
+            counts
+                .entry(word)
+                .and_modify(|c| *c += 1)
+                .or_insert(i as u32, 1);
+        }
+        assert_eq!(
+            vec![("a", 3), ("b", 2), ("c", 1)],
+            counts.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>()
+        );
+
+        match counts.entry("b") {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(&"b", entry.key());
+                assert_eq!(&0, entry.priority());
+                assert_eq!(2, entry.insert(20));
+                assert_eq!(20, entry.remove());
+            }
+            Entry::Vacant(_) => panic!("b should be occupied"),
+        }
+        match counts.entry("d") {
+            Entry::Vacant(entry) => {
+                assert_eq!(&"d", entry.key());
+                *entry.insert(9, 4) += 1;
+            }
+            Entry::Occupied(_) => panic!("d should be vacant"),
+        }
+        assert_eq!(
+            &mut 3,
+            counts.entry("a").or_insert_with(0, || unreachable!())
+        );
+        assert_eq!(Some((&"d", &5)), counts.peek());
+        assert_eq!(
+            vec!["a", "c", "d"],
+            counts.keys().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(3, counts.len());
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let build = |keys: &[u32], tag: u32| {
+            let mut treap: Treap<u32, u32, u32> = Treap::default();
+            for k in keys {
+                treap.insert(*k, k.wrapping_mul(2654435761), tag);
+            }
+            treap
+        };
+        let a: Vec<u32> = (0..60).filter(|k| k % 2 == 0).collect();
+        let b: Vec<u32> = (0..60).filter(|k| k % 3 == 0).collect();
+        let keys = |treap: Treap<u32, u32, u32>| {
+            let entries = treap.into_vec();
+            entries.into_iter().map(|(k, _)| k).collect::<Vec<_>>()
+        };
+
+        let union = build(&a, 1).union(build(&b, 2), |_, x, y| x * 10 + y);
+        assert_eq!(Some(&12), union.get(&6));
+        assert_eq!(Some(&1), union.get(&4));
+        assert_eq!(Some(&2), union.get(&9));
+        assert_eq!(
+            (0..60)
+                .filter(|k| k % 2 == 0 || k % 3 == 0)
+                .collect::<Vec<_>>(),
+            keys(union)
+        );
+
+        let intersection = build(&a, 1).intersection(build(&b, 2));
+        assert!(intersection.values().all(|v| *v == 1));
+        assert_eq!((0..60).step_by(6).collect::<Vec<_>>(), keys(intersection));
+        // our priorities decide the shape, even where theirs are higher
+        let ours: Treap<u32, u32, u32> = [(1, 1, 1), (2, 5, 1)].into_iter().collect();
+        let theirs: Treap<u32, u32, u32> = [(1, 10, 2), (2, 0, 2)].into_iter().collect();
+        let mut intersection = ours.intersection(theirs);
+        assert_eq!(intersection.peek(), Some((&2, &1)));
+        assert_eq!(intersection.pop(), Some((2, 1)));
+        assert_eq!(intersection.peek(), Some((&1, &1)));
+
+        let difference = build(&a, 1).difference(build(&b, 2));
+        assert_eq!(
+            (0..60)
+                .filter(|k| k % 2 == 0 && k % 3 != 0)
+                .collect::<Vec<_>>(),
+            keys(difference)
+        );
+
+        let sym = build(&a, 1).symmetric_difference(build(&b, 2));
+        assert_eq!(Some(&2), sym.get(&3));
+        assert_eq!(20 + 10, sym.len());
+        assert_eq!(
+            (0..60)
+                .filter(|k| (k % 2 == 0) != (k % 3 == 0))
+                .collect::<Vec<_>>(),
+            keys(sym)
+        );
+
+        // heap order must survive, so popping yields non-increasing priorities
+        let mut union = build(&a, 3).union(build(&b, 4), |_, x, _| x);
+        let mut last = u32::MAX;
+        while let Some((k, _)) = union.pop() {
+            let p = k.wrapping_mul(2654435761);
+            assert!(p <= last);
+            last = p;
+        }
+    }
+
+    #[test]
+    fn test_split_off_append() {
+        let mut treap: Treap<u32, u32, u32> = Treap::default();
+        for k in 0..100 {
+            treap.insert(k, k.wrapping_mul(2654435761), k);
+        }
+        let keys = |treap: &Treap<u32, u32, u32>| treap.keys().copied().collect::<Vec<_>>();
+
+        let mut upper = treap.split_off(&60);
+        assert_eq!((0..60).collect::<Vec<_>>(), keys(&treap));
+        assert_eq!((60..100).collect::<Vec<_>>(), keys(&upper));
+
+        let mut middle = treap.split_range(20..=39);
+        assert_eq!((20..40).collect::<Vec<_>>(), keys(&middle));
+        assert_eq!(40, treap.len());
+
+        assert_eq!(Err(TreapError::OverlappingKeys), middle.append(&mut treap));
+        assert_eq!(20, middle.len());
+        assert_eq!(40, treap.len());
+
+        let mut high = treap.split_off(&40);
+        assert_eq!(Ok(()), treap.append(&mut middle));
+        assert_eq!(Ok(()), treap.append(&mut high));
+        assert_eq!(Ok(()), treap.append(&mut upper));
+        assert!(middle.is_empty() && high.is_empty() && upper.is_empty());
+        assert_eq!((0..100).collect::<Vec<_>>(), keys(&treap));
+        assert_eq!(Some((&50, &50)), treap.nth(50));
+
+        let mut empty = Treap::new();
+        assert_eq!(Ok(()), treap.append(&mut empty));
+        assert_eq!(0, treap.split_range(200..).len());
+    }
+
+    #[test]
+    fn test_borrowed_keys() {
+        let mut treap: Treap<String, u32, u32, Sum<u32>> = Treap::with_monoid();
+        for (i, name) in ["ada", "bob", "cyd", "dan", "eve"].iter().enumerate() {
+            treap.insert(name.to_string(), i as u32 * 7 % 5, i as u32);
+        }
+        assert_eq!(Some(&2), treap.get("cyd"));
+        assert!(treap.contains("eve"));
+        assert_eq!(3, treap.rank("dan"));
+        assert_eq!(
+            Sum(1 + 2),
+            treap.aggregate::<str, _>((Bound::Included("b"), Bound::Included("cyd")))
+        );
+        assert_eq!(
+            2,
+            treap
+                .range::<str, _>((Bound::Included("bob"), Bound::Excluded("dan")))
+                .count()
+        );
+        assert_eq!(Some(("ada".to_string(), 0)), treap.erase("ada"));
+        let upper = treap.split_off("d");
+        assert_eq!(
+            vec!["dan", "eve"],
+            upper.keys().map(String::as_str).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_update_priority() {
+        let mut treap: Treap<char, i32, u32> = Treap::default();
+        for (i, c) in "abcdef".chars().enumerate() {
+            treap.insert(c, i as i32, i as u32);
+        }
+        assert_eq!(treap.peek(), Some((&'f', &5)));
+        assert_eq!(treap.update_priority(&'b', 10), Some(1));
+        assert_eq!(treap.update_priority(&'z', 10), None);
+        assert_eq!(treap.peek(), Some((&'b', &1)));
+        // decrease-key, as in Dijkstra with priorities negated distances
+        assert_eq!(treap.update_priority_with(&'b', |p| *p -= 20), Some(()));
+        assert_eq!(treap.update_priority_with(&'z', |p| *p -= 20), None);
+        assert_eq!(treap.len(), 6);
+        assert_eq!(treap.keys().copied().collect::<String>(), "abcdef");
+        let order: Vec<_> = treap.into_iter_by_priority().map(|(k, _)| k).collect();
+        assert_eq!(order, vec!['f', 'e', 'd', 'c', 'a', 'b']);
+    }
+
+    #[test]
+    fn test_priority_search() {
+        let mut treap: Treap<u32, u32, u32> = Treap::default();
+        for i in 0..100 {
+            treap.insert(i, (i * 37) % 101, i * 10);
+        }
+        let mut expected: Vec<_> = (20..40).collect();
+        expected.sort_by_key(|&i| std::cmp::Reverse((i * 37) % 101));
+
+        assert_eq!(
+            treap.peek_in_range(20..40),
+            Some((&expected[0], &(expected[0] * 10)))
+        );
+        assert_eq!(treap.peek_in_range(200..300), None);
+        let top: Vec<_> = treap.top_k_in_range(20..40, 5).map(|(k, _)| *k).collect();
+        assert_eq!(top, expected[..5]);
+        assert_eq!(treap.top_k_in_range(20..40, 50).len(), 20);
+        assert_eq!(treap.top_k_in_range(.., 3).next(), treap.peek());
+
+        for &i in &expected[..3] {
+            assert_eq!(treap.pop_in_range(20..40), Some((i, i * 10)));
+        }
+        assert_eq!(treap.len(), 97);
+        assert_eq!(treap.pop_in_range(200..), None);
+        assert!(!treap.contains(&expected[0]));
+        assert_eq!(
+            treap.top_k_in_range(20..40, 1).next().unwrap().0,
+            &expected[3]
+        );
+    }
+
+    #[test]
+    fn test_from_sorted_iter() {
+        let entries: Vec<_> = (0..1000u32).map(|i| (i, (i * 7919) % 1009, i)).collect();
+        let mut built: Treap<u32, u32, u32, Sum<u32>> =
+            Treap::from_sorted_iter(entries.iter().copied()).unwrap();
+        let mut inserted: Treap<u32, u32, u32, Sum<u32>> = Treap::with_monoid();
+        for &(k, p, v) in &entries {
+            inserted.insert(k, p, v);
+        }
+        assert_eq!(built.len(), 1000);
+        assert_eq!(built.aggregate(10..20), Sum(145));
+        assert_eq!(built.rank(&500), 500);
+        while let Some(entry) = inserted.pop() {
+            assert_eq!(built.pop(), Some(entry));
+        }
+
+        let unsorted = vec![(1, 0, ()), (3, 0, ()), (3, 0, ())];
+        assert_eq!(
+            Treap::<i32, i32, ()>::from_sorted_iter(unsorted).err(),
+            Some(TreapError::UnsortedKeys)
+        );
+        // equal priorities degenerate into a path, which must not overflow
+        let path: Treap<u32, (), ()> =
+            Treap::from_sorted_iter((0..100_000).map(|i| (i, (), ()))).unwrap();
+        assert_eq!(path.len(), 100_000);
+    }
+
+    #[test]
+    fn test_cursor() {
+        let mut treap: Treap<i32, i32, &str> = Treap::default();
+        for (i, k) in [10, 20, 30, 40].into_iter().enumerate() {
+            treap.insert(k, i as i32, "");
+        }
+        let mut cursor = treap.lower_bound_cursor(&25);
+        assert_eq!(cursor.key(), Some(&30));
+        cursor.move_next();
+        cursor.move_next();
+        assert_eq!((cursor.key(), cursor.index()), (None, None));
+        cursor.move_next();
+        assert_eq!(cursor.key(), Some(&10));
+        cursor.move_prev();
+        cursor.move_prev();
+        assert_eq!(cursor.key(), Some(&40));
+        assert_eq!(treap.lower_bound_cursor(&41).key(), None);
+
+        let mut cursor = treap.lower_bound_cursor_mut(&20);
+        *cursor.value_mut().unwrap() = "twenty";
+        assert_eq!(
+            cursor.insert_before(20, 9, "dup"),
+            Err(TreapError::UnsortedKeys)
+        );
+        assert_eq!(
+            cursor.insert_before(5, 9, "low"),
+            Err(TreapError::UnsortedKeys)
+        );
+        assert_eq!(cursor.insert_before(15, 9, "fifteen"), Ok(()));
+        assert_eq!(cursor.insert_after(25, 9, "twenty-five"), Ok(()));
+        assert_eq!(
+            cursor.insert_after(35, 9, "high"),
+            Err(TreapError::UnsortedKeys)
+        );
+        assert_eq!(cursor.key(), Some(&20));
+        assert_eq!(cursor.remove_current(), Some((20, "twenty")));
+        assert_eq!(cursor.key(), Some(&25));
+        cursor.move_prev();
+        cursor.move_prev();
+        cursor.move_prev();
+        assert_eq!(cursor.index(), None);
+        assert_eq!(cursor.remove_current(), None);
+        assert_eq!(cursor.insert_after(1, 9, "front"), Ok(()));
+        assert_eq!(cursor.insert_before(50, 9, "back"), Ok(()));
+        assert_eq!(cursor.index(), None);
+        assert_eq!(
+            treap.keys().copied().collect::<Vec<_>>(),
+            vec![1, 10, 15, 25, 30, 40, 50]
+        );
+        assert_eq!(treap.get(&15), Some(&"fifteen"));
+    }
+
+    #[test]
+    fn test_neighbours() {
+        let mut treap: Treap<i32, i32, char> = Treap::default();
+        for (k, p, v) in [(10, 1, 'a'), (20, 5, 'b'), (30, 3, 'c'), (40, 4, 'd')] {
+            treap.insert(k, p, v);
+        }
+        assert_eq!(treap.first_key_value(), Some((&10, &'a')));
+        assert_eq!(treap.last_key_value(), Some((&40, &'d')));
+        assert_eq!(treap.floor(&20), Some((&20, &'b')));
+        assert_eq!(treap.floor(&25), Some((&20, &'b')));
+        assert_eq!(treap.floor(&5), None);
+        assert_eq!(treap.ceiling(&25), Some((&30, &'c')));
+        assert_eq!(treap.ceiling(&30), Some((&30, &'c')));
+        assert_eq!(treap.ceiling(&45), None);
+        assert_eq!(treap.predecessor(&20), Some((&10, &'a')));
+        assert_eq!(treap.predecessor(&10), None);
+        assert_eq!(treap.successor(&20), Some((&30, &'c')));
+        assert_eq!(treap.successor(&40), None);
+
+        assert_eq!(treap.pop_first(), Some((10, 'a')));
+        assert_eq!(treap.pop_last(), Some((40, 'd')));
+        assert_eq!(treap.pop(), Some((20, 'b')));
+        assert_eq!(treap.pop_last(), Some((30, 'c')));
+        assert_eq!(treap.pop_first(), None);
+        assert_eq!(treap.pop_last(), None);
+        assert_eq!(treap.first_key_value(), None);
+    }
+
+    #[test]
+    fn test_retain_extract_drain() {
+        let build = || {
+            let mut treap: Treap<u32, u32, u32, Sum<u32>> = Treap::with_monoid();
+            for i in 0..100 {
+                treap.insert(i, (i * 37) % 101, i);
+            }
+            treap
+        };
+        let by_priority = |treap: Treap<u32, u32, u32, Sum<u32>>| {
+            treap
+                .into_iter_by_priority()
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>()
+        };
+
+        let mut treap = build();
+        treap.retain(|k, v| {
+            *v *= 2;
+            k % 3 == 0
+        });
+        assert_eq!(treap.len(), 34);
+        assert_eq!(
+            treap.aggregate(..),
+            Sum((0..100).filter(|k| k % 3 == 0).map(|k| k * 2).sum())
+        );
+        assert!(treap.keys().copied().eq((0..100).filter(|k| k % 3 == 0)));
+        let mut expected: Vec<_> = (0..100).filter(|k| k % 3 == 0).collect();
+        expected.sort_by_key(|k| std::cmp::Reverse((k * 37) % 101));
+        assert_eq!(by_priority(treap), expected);
+
+        let mut treap = build();
+        let odd: Vec<_> = treap
+            .extract_if(|k, _| k % 2 == 1)
+            .map(|(k, _)| k)
+            .collect();
+        assert!(odd.into_iter().eq((1..100).step_by(2)));
+        assert!(treap.keys().copied().eq((0..100).step_by(2)));
+        // dropping the iterator early keeps the entries it didn't reach
+        let first: Vec<_> = treap.extract_if(|_, _| true).take(3).collect();
+        assert_eq!(first, vec![(0, 0), (2, 2), (4, 4)]);
+        assert_eq!(treap.len(), 47);
+        assert_eq!(treap.rank(&50), 22);
+
+        let mut treap = build();
+        let mut drained = treap.drain(10..20);
+        assert_eq!(drained.len(), 10);
+        assert_eq!(drained.next(), Some((10, 10)));
+        assert!(drained.map(|(k, _)| k).eq(11..20));
+        assert_eq!(treap.len(), 90);
+        assert_eq!(treap.ceiling(&10), Some((&20, &20)));
+    }
+
+    #[test]
+    fn test_std_traits() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        #[derive(Default)]
+        struct Index {
+            by_name: Treap<String, u32, usize>,
+            by_age: MinTreap<u32, u32, u64, Sum<u64>>,
+        }
+        let mut index = Index::default();
+        index.by_age.insert(30, 5, 2);
+        index.by_age.insert(40, 1, 3);
+        assert_eq!(index.by_age.aggregate(..), Sum(5));
+        assert_eq!(index.by_age.peek(), Some((&40, &3)));
+        index
+            .by_name
+            .extend([("b".to_string(), 1, 2), ("a".to_string(), 2, 1)]);
+        assert_eq!(index.by_name["a"], 1);
+        assert_eq!(format!("{:?}", index.by_name), r#"{"a": 1, "b": 2}"#);
+
+        let treap: Treap<i32, i32, i32> = (0..50).map(|i| (i, i % 7, i * i)).collect();
+        // same entries, different priorities and shape
+        let other: Treap<i32, i32, i32> = (0..50).rev().map(|i| (i, 0, i * i)).collect();
+        let hash = |t: &Treap<i32, i32, i32>| {
+            let mut hasher = DefaultHasher::new();
+            t.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(treap, other);
+        assert_eq!(hash(&treap), hash(&other));
+
+        let mut copy = treap.clone();
+        for (_, v) in &mut copy {
+            *v += 1;
+        }
+        assert_ne!(copy, treap);
+        assert_eq!((&copy).into_iter().next(), Some((&0, &1)));
+        assert_eq!(
+            copy.into_iter().map(|(_, v)| v).sum::<i32>(),
+            treap.values().sum::<i32>() + 50
+        );
+        assert!(treap.into_iter().map(|(k, _)| k).eq(0..50));
+
+        // pending lazy updates are cloned along with the tree
+        let mut lazy: Treap<i32, i32, i64, Sum<i64>, RangeUpdate<i64>> =
+            (0..10).map(|i| (i, i, 1)).collect();
+        lazy.update_range(2..8, RangeUpdate::Add(10));
+        let mut lazy_copy = lazy.clone();
+        assert_eq!(lazy_copy.aggregate(..), Sum(70));
+        assert_eq!(lazy_copy.lookup(&5), Some(&11));
+        assert!(lazy.into_iter().eq(lazy_copy.into_iter()));
+    }
+
+    #[test]
+    fn test_custom_order() {
+        let mut min: MinTreap<i32, u32, ()> = Treap::with_order();
+        for (k, p) in [(1, 30), (2, 10), (3, 20), (4, 40)] {
+            min.insert(k, p, ());
+        }
+        assert_eq!(min.peek(), Some((&2, &())));
+        let popped: Vec<_> = std::iter::from_fn(|| min.pop()).map(|(k, _)| k).collect();
+        assert_eq!(popped, vec![2, 3, 1, 4]);
+
+        struct CaseInsensitive;
+        impl Compare<str> for CaseInsensitive {
+            fn compare(a: &str, b: &str) -> Ordering {
+                let (a, b) = (a.bytes(), b.bytes());
+                a.map(|c| c.to_ascii_lowercase())
+                    .cmp(b.map(|c| c.to_ascii_lowercase()))
+            }
+        }
+        impl Compare<String> for CaseInsensitive {
+            fn compare(a: &String, b: &String) -> Ordering {
+                <Self as Compare<str>>::compare(a, b)
+            }
+        }
+        let mut words: Treap<String, u32, i32, (), (), CaseInsensitive> = Treap::with_order();
+        words.insert("banana".to_string(), 1, 1);
+        words.insert("Apple".to_string(), 2, 2);
+        words.insert("cherry".to_string(), 3, 3);
+        assert!(words
+            .keys()
+            .map(String::as_str)
+            .eq(["Apple", "banana", "cherry"]));
+        assert_eq!(words.get("APPLE"), Some(&2));
+        assert_eq!(
+            words.insert("BANANA".to_string(), 4, 5),
+            Some(("banana".to_string(), 1))
+        );
+        assert_eq!(words.len(), 3);
+        assert_eq!(words.ceiling("B"), Some((&"BANANA".to_string(), &5)));
+
+        // ordering by a projection lets keys without `Ord` in
+        struct TotalOrder;
+        impl Compare<f64> for TotalOrder {
+            fn compare(a: &f64, b: &f64) -> Ordering {
+                a.total_cmp(b)
+            }
+        }
+        let floats: Treap<f64, f64, (), (), (), TotalOrder, TotalOrder> = [2.5, -1.0, 0.5, 10.0]
+            .iter()
+            .map(|&x| (x, -x, ()))
+            .collect();
+        assert!(floats.keys().copied().eq([-1.0, 0.5, 2.5, 10.0]));
+        assert_eq!(floats.peek(), Some((&-1.0, &())));
+        assert_eq!(floats.rank(&1.0), 2);
+    }
+
+    #[test]
+    fn test_render() {
+        let treap: Treap<i32, i32, &str> = [(2, 9, "b"), (1, 5, "a"), (4, 7, "d"), (3, 1, "c")]
+            .into_iter()
+            .collect();
+        let tree = "\
+2 (p=9): \"b\"
+├── L 1 (p=5): \"a\"
+└── R 4 (p=7): \"d\"
+    └── L 3 (p=1): \"c\"
+";
+        assert_eq!(treap.to_string(), tree);
+        let dot = treap.to_dot();
+        assert!(dot.starts_with("digraph treap {\n"));
+        assert!(dot.contains("n0 [label=\"2\\np=9\\n\\\"b\\\"\"];"));
+        assert!(dot.contains("n0 -> n1 [label=\"L\"];"));
+        assert!(dot.contains("n2 -> n3 [label=\"L\"];"));
+        assert_eq!(dot.matches("->").count(), 3);
+        assert_eq!(Treap::<i32, i32, ()>::default().to_string(), "(empty)\n");
+
+        let mut sums: Treap<i32, i32, i32, Sum<i32>, RangeUpdate<i32>> =
+            [(2, 9, 20), (1, 5, 10), (4, 7, 40), (3, 1, 30)]
+                .into_iter()
+                .collect();
+        sums.update_range(3..=4, RangeUpdate::Add(1));
+        // the tag waits on 4, so 3 still shows its old value
+        let tree = "\
+2 (p=9, sum=Sum(102)): 20
+├── L 1 (p=5, sum=Sum(10)): 10
+└── R 4 (p=7, sum=Sum(72), tag=Add(1)): 41
+    └── L 3 (p=1, sum=Sum(30)): 30
+";
+        assert_eq!(sums.to_string(), tree);
+        assert!(sums
+            .to_dot()
+            .contains("[label=\"4\\np=7\\n41\\nsum=Sum(72)\\ntag=Add(1)\"];"));
+
+        // a degenerate treap renders without recursing
+        let path: Treap<u32, u32, ()> = (0..100_000).map(|i| (i, i, ())).collect();
+        assert_eq!(path.to_dot().matches("->").count(), 99_999);
+        let path: Treap<u32, u32, ()> = (0..1000).map(|i| (i, i, ())).collect();
+        assert_eq!(path.to_string().lines().count(), 1000);
+    }
+
+    #[test]
+    fn test_degenerate_priorities() {
+        // priorities increasing with the keys turn the treap into a single
+        // left-leaning path, deep enough to overflow a recursive walk
+        let n = 200_000u32;
+        let mut treap: Treap<u32, u32, u32> = Treap::default();
+        for k in 0..n {
+            treap.insert(k, k, k);
+        }
+        assert_eq!(Some(&0), treap.get(&0));
+        assert_eq!(Some((0, 0)), treap.erase(&0));
+        assert!(!treap.contains(&0));
+
+        let mut upper = treap.split_off(&(n / 2));
+        assert_eq!((n / 2 - 1) as usize, treap.len());
+        assert_eq!(Ok(()), treap.append(&mut upper));
+        assert_eq!(Some((n - 1, n - 1)), treap.pop());
+
+        assert_eq!(10, treap.range(10..20).count());
+        assert_eq!(Some((&(n - 2), &(n - 2))), treap.range(n / 2..).next_back());
+        for (_, v) in treap.range_mut(..=5) {
+            *v = 0;
+        }
+        assert_eq!(Some(&0), treap.get(&5));
+        let top: Vec<_> = treap.top_k_in_range(100..n, 2).map(|(k, _)| *k).collect();
+        assert_eq!(vec![n - 2, n - 3], top);
+
+        let entries = treap.into_vec();
+        assert_eq!((n - 2) as usize, entries.len());
+        assert_eq!(Some(&(1, 0)), entries.first());
+
+        let mut sums: Treap<u32, u32, i64, Sum<i64>, RangeUpdate<i64>> = Treap::with_monoid();
+        for k in 0..n {
+            sums.insert(k, k, 1);
+        }
+        sums.update_range(..n / 2, RangeUpdate::Add(1));
+        assert_eq!(Sum(30), sums.aggregate(n / 2 - 10..n / 2 + 10));
+        assert_eq!(Sum(i64::from(n) * 3 / 2), sums.aggregate(..));
+
+        let path = |keys: &mut dyn Iterator<Item = u32>| {
+            let mut treap: Treap<u32, u32, u32> = Treap::default();
+            keys.for_each(|k| {
+                treap.insert(k, k, k);
+            });
+            treap
+        };
+        let evens = || path(&mut (0..n).step_by(2));
+        let thirds = || path(&mut (0..n).step_by(3));
+        let sixths = (n as usize).div_ceil(6);
+        assert_eq!(
+            (n as usize).div_ceil(2) + (n as usize).div_ceil(3) - sixths,
+            evens().union(thirds(), |_, a, _| a).len()
+        );
+        assert_eq!(sixths, evens().intersection(thirds()).len());
+        assert_eq!(
+            (n as usize).div_ceil(2) - sixths,
+            evens().difference(thirds()).len()
+        );
+        assert_eq!(
+            (n as usize).div_ceil(2) + (n as usize).div_ceil(3) - 2 * sixths,
+            evens().symmetric_difference(thirds()).len()
+        );
+
+        let mut dropped: Treap<u32, u32, ()> = Treap::default();
+        for k in 0..n {
+            dropped.insert(k, k, ());
+        }
+    }
 }
@@ -0,0 +1,190 @@
+use crate::compare::Natural;
+use crate::monoid::{Monoid, Update};
+use crate::treap::storage::{Linked, Sealed, Storage};
+use crate::treap::TreapNodePtr;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+
+/// All nodes of a treap in one `Vec`, linked by `u32` index; the storage of
+/// an [`ArenaTreap`](crate::arena_treap::ArenaTreap).
+///
+/// Removed nodes leave their slot on a free list, which later inserts reuse.
+/// Dropping the treap drops the `Vec` as a whole.
+pub struct Arena;
+
+impl Storage for Arena {}
+
+/// Index of a slot plus one, so that a missing child costs no extra space.
+#[derive(Clone, Copy)]
+pub struct NodeId(NonZeroU32);
+
+impl NodeId {
+    fn index(self) -> usize {
+        self.0.get() as usize - 1
+    }
+}
+
+pub enum Slot<N> {
+    Node(N),
+    /// A free slot, linking to the next one on the free list.
+    Free(Option<NodeId>),
+}
+
+pub struct Slots<N> {
+    slots: Vec<Slot<N>>,
+    /// Most recently freed slot.
+    free: Option<NodeId>,
+}
+
+impl<N> Default for Slots<N> {
+    fn default() -> Self {
+        Slots {
+            slots: Vec::new(),
+            free: None,
+        }
+    }
+}
+
+impl<N> Slots<N> {
+    fn node(&self, id: NodeId) -> &N {
+        match &self.slots[id.index()] {
+            Slot::Node(node) => node,
+            Slot::Free(_) => unreachable!("link to a free slot"),
+        }
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut N {
+        match &mut self.slots[id.index()] {
+            Slot::Node(node) => node,
+            Slot::Free(_) => unreachable!("link to a free slot"),
+        }
+    }
+}
+
+/// The slots not yet opened by an `IterMut`, as runs keyed by their first
+/// index. Opening a slot splits its run around it.
+pub struct SlotsMut<'a, N> {
+    runs: BTreeMap<usize, &'a mut [Slot<N>]>,
+}
+
+impl Sealed for Arena {
+    type Handle<N> = NodeId;
+    type Store<N> = Slots<N>;
+    type NodeRef<'a, N: 'a> = NodeId;
+    type Nodes<'a, N: 'a> = SlotsMut<'a, N>;
+
+    const STORE_OWNS_NODES: bool = true;
+
+    fn alloc<N>(store: &mut Slots<N>, node: N) -> NodeId {
+        if let Some(id) = store.free {
+            match std::mem::replace(&mut store.slots[id.index()], Slot::Node(node)) {
+                Slot::Free(next) => store.free = next,
+                Slot::Node(_) => unreachable!("free list holds a node"),
+            }
+            return id;
+        }
+        let id = u32::try_from(store.slots.len() + 1)
+            .ok()
+            .and_then(NonZeroU32::new)
+            .unwrap_or_else(|| panic!("ArenaTreap can hold at most {} entries", u32::MAX));
+        store.slots.push(Slot::Node(node));
+        NodeId(id)
+    }
+
+    fn free<N>(store: &mut Slots<N>, id: NodeId) -> N {
+        let slot = std::mem::replace(&mut store.slots[id.index()], Slot::Free(store.free));
+        store.free = Some(id);
+        match slot {
+            Slot::Node(node) => node,
+            Slot::Free(_) => unreachable!("slot freed twice"),
+        }
+    }
+
+    fn map<N, F: FnOnce(N) -> N>(store: &mut Slots<N>, id: NodeId, f: F) -> NodeId {
+        let slot = &mut store.slots[id.index()];
+        match std::mem::replace(slot, Slot::Free(None)) {
+            Slot::Node(node) => *slot = Slot::Node(f(node)),
+            Slot::Free(_) => unreachable!("link to a free slot"),
+        }
+        id
+    }
+
+    fn get<'a, N>(store: &'a Slots<N>, id: &'a NodeId) -> &'a N {
+        store.node(*id)
+    }
+
+    fn get_mut<'a, N>(store: &'a mut Slots<N>, id: &'a mut NodeId) -> &'a mut N {
+        store.node_mut(*id)
+    }
+
+    fn descend_mut<'a, N: Linked<Self>, F>(
+        store: &'a mut Slots<N>,
+        link: &'a mut Option<NodeId>,
+        mut step: F,
+    ) -> Option<&'a mut N>
+    where
+        F: FnMut(&mut Slots<N>, &mut NodeId) -> Ordering,
+    {
+        let mut id = (*link)?;
+        loop {
+            match step(store, &mut id) {
+                Ordering::Equal => return Some(store.node_mut(id)),
+                ord => id = (*store.node(id).child(ord.is_lt()))?,
+            }
+        }
+    }
+
+    fn nodes<N>(store: &mut Slots<N>) -> SlotsMut<'_, N> {
+        SlotsMut {
+            runs: BTreeMap::from([(0, &mut store.slots[..])]),
+        }
+    }
+
+    fn node_ref<N>(link: &mut Option<NodeId>) -> Option<NodeId> {
+        *link
+    }
+
+    fn open<'a, N: 'a>(nodes: &mut SlotsMut<'a, N>, id: NodeId) -> &'a mut N {
+        let i = id.index();
+        let start = match nodes.runs.range(..=i).next_back() {
+            Some((&start, run)) if i - start < run.len() => start,
+            _ => panic!("slot opened twice"),
+        };
+        let run = nodes.runs.remove(&start).unwrap();
+        let (before, rest) = run.split_at_mut(i - start);
+        let (slot, after) = rest.split_first_mut().unwrap();
+        if !before.is_empty() {
+            nodes.runs.insert(start, before);
+        }
+        if !after.is_empty() {
+            nodes.runs.insert(i + 1, after);
+        }
+        match slot {
+            Slot::Node(node) => node,
+            Slot::Free(_) => unreachable!("link to a free slot"),
+        }
+    }
+}
+
+impl<K, P, V> TreapNodePtr<K, P, V, (), (), Natural, Natural, Arena> {
+    /// Creates an empty treap with room for `cap` entries before the arena
+    /// grows.
+    pub fn with_capacity(cap: usize) -> Self {
+        TreapNodePtr {
+            root: None,
+            store: Slots {
+                slots: Vec::with_capacity(cap),
+                free: None,
+            },
+        }
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC, PC> TreapNodePtr<K, P, V, M, U, KC, PC, Arena> {
+    /// Number of slots handed out so far, free or not.
+    #[cfg(test)]
+    pub(crate) fn slots_used(&self) -> usize {
+        self.store.slots.len()
+    }
+}
@@ -0,0 +1,168 @@
+use crate::compare::{Compare, Natural};
+use crate::treap::storage::{Boxed, Storage};
+use crate::treap::{TreapError, TreapNodePtr};
+use std::borrow::Borrow;
+
+/// Read-only position in a treap, created by `Treap::lower_bound_cursor`.
+///
+/// Points at an entry or at the "ghost" position past the last entry, which
+/// sits between the last and the first entry when moving around. Like an
+/// `Entry` it remembers a position in key order rather than a node, so each
+/// access re-walks the path in O(log n).
+pub struct Cursor<'a, K, P, V, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    treap: &'a TreapNodePtr<K, P, V, (), (), KC, PC, S>,
+    index: usize,
+}
+
+/// Position in a treap that can modify it, created by
+/// `Treap::lower_bound_cursor_mut`. See [`Cursor`] for how it moves.
+pub struct CursorMut<'a, K, P, V, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    treap: &'a mut TreapNodePtr<K, P, V, (), (), KC, PC, S>,
+    index: usize,
+}
+
+impl<K, P, V, KC: Compare<K>, PC: Compare<P>, S: Storage> TreapNodePtr<K, P, V, (), (), KC, PC, S> {
+    /// Returns a cursor at the first entry with a key `>= key`, or at the
+    /// ghost position if there is none.
+    pub fn lower_bound_cursor<Q>(&self, key: &Q) -> Cursor<'_, K, P, V, KC, PC, S>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        Cursor {
+            index: self.rank(key),
+            treap: self,
+        }
+    }
+
+    /// Like `lower_bound_cursor`, but the cursor can modify the treap.
+    pub fn lower_bound_cursor_mut<Q>(&mut self, key: &Q) -> CursorMut<'_, K, P, V, KC, PC, S>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        CursorMut {
+            index: self.rank(key),
+            treap: self,
+        }
+    }
+}
+
+impl<'a, K, P, V, KC: Compare<K>, PC: Compare<P>, S: Storage> Cursor<'a, K, P, V, KC, PC, S> {
+    /// Returns the position of the current entry in key order, or `None` at
+    /// the ghost position.
+    pub fn index(&self) -> Option<usize> {
+        (self.index < self.treap.len()).then_some(self.index)
+    }
+
+    pub fn key(&self) -> Option<&'a K> {
+        self.index().map(|i| &self.treap.nth_node(i).key)
+    }
+
+    pub fn value(&self) -> Option<&'a V> {
+        self.index().map(|i| &self.treap.nth_node(i).value)
+    }
+
+    /// Moves to the next entry; from the last entry this is the ghost
+    /// position, and from there the first entry.
+    pub fn move_next(&mut self) {
+        self.index = if self.index == self.treap.len() {
+            0
+        } else {
+            self.index + 1
+        };
+    }
+
+    /// Moves to the previous entry; from the first entry this is the ghost
+    /// position, and from there the last entry.
+    pub fn move_prev(&mut self) {
+        self.index = match self.index {
+            0 => self.treap.len(),
+            i => i - 1,
+        };
+    }
+}
+
+impl<K, P, V, KC: Compare<K>, PC: Compare<P>, S: Storage> CursorMut<'_, K, P, V, KC, PC, S> {
+    /// Returns the position of the current entry in key order, or `None` at
+    /// the ghost position.
+    pub fn index(&self) -> Option<usize> {
+        (self.index < self.treap.len()).then_some(self.index)
+    }
+
+    pub fn key(&self) -> Option<&K> {
+        self.index().map(|i| &self.treap.nth_node(i).key)
+    }
+
+    pub fn value(&self) -> Option<&V> {
+        self.index().map(|i| &self.treap.nth_node(i).value)
+    }
+
+    pub fn value_mut(&mut self) -> Option<&mut V> {
+        let i = self.index()?;
+        self.treap.value_at_mut(i)
+    }
+
+    /// See `Cursor::move_next`.
+    pub fn move_next(&mut self) {
+        self.index = if self.index == self.treap.len() {
+            0
+        } else {
+            self.index + 1
+        };
+    }
+
+    /// See `Cursor::move_prev`.
+    pub fn move_prev(&mut self) {
+        self.index = match self.index {
+            0 => self.treap.len(),
+            i => i - 1,
+        };
+    }
+
+    /// Removes the current entry and moves to the next one. Does nothing at
+    /// the ghost position.
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        let i = self.index()?;
+        Some(self.treap.remove_at_index(i))
+    }
+
+    /// Inserts an entry just before the current one (at the end when at the
+    /// ghost position) and stays on the current entry.
+    ///
+    /// Fails with `UnsortedKeys` unless `key` lies strictly between the keys
+    /// of the neighbouring entries.
+    pub fn insert_before(&mut self, key: K, priority: P, value: V) -> Result<(), TreapError> {
+        self.insert_at(self.index, key, priority, value)
+    }
+
+    /// Inserts an entry just after the current one (at the front when at the
+    /// ghost position) and stays on the current entry.
+    ///
+    /// Fails with `UnsortedKeys` unless `key` lies strictly between the keys
+    /// of the neighbouring entries.
+    pub fn insert_after(&mut self, key: K, priority: P, value: V) -> Result<(), TreapError> {
+        let pos = if self.index == self.treap.len() {
+            0
+        } else {
+            self.index + 1
+        };
+        self.insert_at(pos, key, priority, value)
+    }
+
+    fn insert_at(&mut self, pos: usize, key: K, priority: P, value: V) -> Result<(), TreapError> {
+        let len = self.treap.len();
+        let after_prev = pos == 0 || KC::compare(&self.treap.nth_node(pos - 1).key, &key).is_lt();
+        let before_next = pos == len || KC::compare(&key, &self.treap.nth_node(pos).key).is_lt();
+        if !(after_prev && before_next) {
+            return Err(TreapError::UnsortedKeys);
+        }
+        self.treap.insert_at_index(pos, key, priority, value);
+        if pos <= self.index {
+            self.index += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use crate::compare::{Compare, Natural};
+use crate::treap::storage::{Boxed, Storage};
+use crate::treap::{TreapNode, TreapNodePtr};
+use std::cmp::Ordering;
+
+/// View into a single key of a treap, created by `Treap::entry`.
+///
+/// An entry remembers the key's position in key order rather than a node, so
+/// each access re-walks the path in O(log n); inserting or removing goes
+/// through a split/merge.
+pub enum Entry<'a, K, P, V, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    Vacant(VacantEntry<'a, K, P, V, KC, PC, S>),
+    Occupied(OccupiedEntry<'a, K, P, V, KC, PC, S>),
+}
+
+pub struct VacantEntry<'a, K, P, V, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    treap: &'a mut TreapNodePtr<K, P, V, (), (), KC, PC, S>,
+    key: K,
+    /// Position the key will take in key order.
+    index: usize,
+}
+
+pub struct OccupiedEntry<'a, K, P, V, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    treap: &'a mut TreapNodePtr<K, P, V, (), (), KC, PC, S>,
+    /// Position of the key in key order.
+    index: usize,
+}
+
+impl<K, P, V, KC: Compare<K>, PC: Compare<P>, S: Storage> TreapNodePtr<K, P, V, (), (), KC, PC, S> {
+    pub(super) fn nth_node(&self, mut i: usize) -> &TreapNode<K, P, V, (), (), KC, PC, S> {
+        let mut curr = &self.root;
+        while let Some(node) = S::node(&self.store, curr) {
+            let left_len = self.len_in(&node.left);
+            match i.cmp(&left_len) {
+                Ordering::Less => curr = &node.left,
+                Ordering::Equal => return node,
+                Ordering::Greater => {
+                    i -= left_len + 1;
+                    curr = &node.right;
+                }
+            }
+        }
+        unreachable!("index out of bounds")
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, P, V, KC, PC, S> {
+        let mut index = 0;
+        let mut found = false;
+        let mut curr = &self.root;
+        while let Some(node) = S::node(&self.store, curr) {
+            match KC::compare(&node.key, &key) {
+                Ordering::Less => {
+                    index += self.len_in(&node.left) + 1;
+                    curr = &node.right;
+                }
+                Ordering::Equal => {
+                    index += self.len_in(&node.left);
+                    found = true;
+                    break;
+                }
+                Ordering::Greater => curr = &node.left,
+            }
+        }
+        if found {
+            Entry::Occupied(OccupiedEntry { treap: self, index })
+        } else {
+            Entry::Vacant(VacantEntry {
+                treap: self,
+                key,
+                index,
+            })
+        }
+    }
+}
+
+impl<'a, K, P, V, KC: Compare<K>, PC: Compare<P>, S: Storage> Entry<'a, K, P, V, KC, PC, S> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Vacant(entry) => entry.key(),
+            Entry::Occupied(entry) => entry.key(),
+        }
+    }
+
+    /// Inserts `default` with `priority` if the key is vacant.
+    pub fn or_insert(self, priority: P, default: V) -> &'a mut V {
+        match self {
+            Entry::Vacant(entry) => entry.insert(priority, default),
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, priority: P, default: F) -> &'a mut V {
+        match self {
+            Entry::Vacant(entry) => entry.insert(priority, default()),
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K, P, V, KC: Compare<K>, PC: Compare<P>, S: Storage> VacantEntry<'a, K, P, V, KC, PC, S> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, priority: P, value: V) -> &'a mut V {
+        self.treap
+            .insert_at_index(self.index, self.key, priority, value);
+        self.treap
+            .value_at_mut(self.index)
+            .expect("entry was just inserted")
+    }
+}
+
+impl<'a, K, P, V, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    OccupiedEntry<'a, K, P, V, KC, PC, S>
+{
+    pub fn key(&self) -> &K {
+        &self.treap.nth_node(self.index).key
+    }
+
+    pub fn priority(&self) -> &P {
+        &self.treap.nth_node(self.index).priority
+    }
+
+    pub fn get(&self) -> &V {
+        &self.treap.nth_node(self.index).value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.treap
+            .value_at_mut(self.index)
+            .expect("occupied entry must exist")
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.treap
+            .value_at_mut(self.index)
+            .expect("occupied entry must exist")
+    }
+
+    /// Replaces the value, keeping the key and priority.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        self.treap.remove_at_index(self.index)
+    }
+}
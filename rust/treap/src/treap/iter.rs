@@ -0,0 +1,691 @@
+use crate::compare::{Compare, Natural};
+use crate::monoid::Monoid;
+use crate::treap::storage::{Boxed, Sealed, Storage};
+use crate::treap::{after_start, before_end, Link, Store, TreapNode, TreapNodePtr};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::iter::FusedIterator;
+use std::ops::Bound;
+
+/// Not yet visited part of the tree. Subtrees are expanded lazily from
+/// whichever end is advanced, so both ends can be walked without aliasing.
+enum Visit<'a, K, P, V, M: Monoid<V>, KC, PC, S: Storage> {
+    Subtree(&'a TreapNode<K, P, V, M, (), KC, PC, S>),
+    Entry(&'a TreapNode<K, P, V, M, (), KC, PC, S>),
+}
+
+/// In-order iterator over the entries of a treap, created by `Treap::iter`.
+pub struct Iter<'a, K, P, V, M: Monoid<V>, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    store: &'a Store<K, P, V, M, (), KC, PC, S>,
+    visits: VecDeque<Visit<'a, K, P, V, M, KC, PC, S>>,
+    remaining: usize,
+}
+
+impl<'a, K, P, V, M: Monoid<V>, KC, PC, S: Storage> Iter<'a, K, P, V, M, KC, PC, S> {
+    pub(super) fn new(treap: &'a TreapNodePtr<K, P, V, M, (), KC, PC, S>) -> Self {
+        let mut visits = VecDeque::new();
+        if let Some(node) = S::node(&treap.store, &treap.root) {
+            visits.push_back(Visit::Subtree(node));
+        }
+        Iter {
+            store: &treap.store,
+            visits,
+            remaining: treap.len(),
+        }
+    }
+
+    /// Starts with the O(log n) whole subtrees and single entries that make
+    /// up the keys within `lo..hi`, so nothing outside the range is visited.
+    pub(super) fn new_range<Q>(
+        treap: &'a TreapNodePtr<K, P, V, M, (), KC, PC, S>,
+        lo: Bound<&Q>,
+        hi: Bound<&Q>,
+    ) -> Self
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        let mut iter = Iter {
+            store: &treap.store,
+            visits: VecDeque::new(),
+            remaining: 0,
+        };
+        iter.push_range(&treap.root, lo, hi);
+        iter
+    }
+
+    /// Walks the two boundary paths of the range in loops rather than
+    /// recursing, since a degenerate tree can be arbitrarily deep.
+    fn push_range<Q>(
+        &mut self,
+        root: &'a Link<K, P, V, M, (), KC, PC, S>,
+        lo: Bound<&Q>,
+        hi: Bound<&Q>,
+    ) where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        // the highest node in the range; all others lie below it
+        let mut curr = root;
+        let split = loop {
+            let node = match S::node(self.store, curr) {
+                None => return,
+                Some(node) => node,
+            };
+            if !after_start::<KC, _, _>(&node.key, lo) {
+                curr = &node.right;
+            } else if !before_end::<KC, _, _>(&node.key, hi) {
+                curr = &node.left;
+            } else {
+                break node;
+            }
+        };
+
+        // in-range parts along the path towards `lo`, deepest last
+        let mut lower = Vec::new();
+        let mut curr = &split.left;
+        if let Bound::Unbounded = lo {
+            lower.extend(S::node(self.store, curr).map(Visit::Subtree));
+        } else {
+            while let Some(node) = S::node(self.store, curr) {
+                if after_start::<KC, _, _>(&node.key, lo) {
+                    lower.extend(S::node(self.store, &node.right).map(Visit::Subtree));
+                    lower.push(Visit::Entry(node));
+                    curr = &node.left;
+                } else {
+                    curr = &node.right;
+                }
+            }
+        }
+        for visit in lower.into_iter().rev() {
+            self.push(visit);
+        }
+        self.push(Visit::Entry(split));
+
+        let mut curr = &split.right;
+        if let Bound::Unbounded = hi {
+            if let Some(node) = S::node(self.store, curr) {
+                self.push(Visit::Subtree(node));
+            }
+            return;
+        }
+        while let Some(node) = S::node(self.store, curr) {
+            if before_end::<KC, _, _>(&node.key, hi) {
+                if let Some(left) = S::node(self.store, &node.left) {
+                    self.push(Visit::Subtree(left));
+                }
+                self.push(Visit::Entry(node));
+                curr = &node.right;
+            } else {
+                curr = &node.left;
+            }
+        }
+    }
+
+    fn push(&mut self, visit: Visit<'a, K, P, V, M, KC, PC, S>) {
+        self.remaining += match visit {
+            Visit::Subtree(node) => node.size,
+            Visit::Entry(_) => 1,
+        };
+        self.visits.push_back(visit);
+    }
+}
+
+impl<'a, K, P, V, M: Monoid<V>, KC, PC, S: Storage> Iterator for Iter<'a, K, P, V, M, KC, PC, S> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.visits.pop_front()? {
+                Visit::Entry(node) => {
+                    self.remaining -= 1;
+                    return Some((&node.key, &node.value));
+                }
+                Visit::Subtree(node) => {
+                    if let Some(right) = S::node(self.store, &node.right) {
+                        self.visits.push_front(Visit::Subtree(right));
+                    }
+                    self.visits.push_front(Visit::Entry(node));
+                    if let Some(left) = S::node(self.store, &node.left) {
+                        self.visits.push_front(Visit::Subtree(left));
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> DoubleEndedIterator
+    for Iter<'_, K, P, V, M, KC, PC, S>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.visits.pop_back()? {
+                Visit::Entry(node) => {
+                    self.remaining -= 1;
+                    return Some((&node.key, &node.value));
+                }
+                Visit::Subtree(node) => {
+                    if let Some(left) = S::node(self.store, &node.left) {
+                        self.visits.push_back(Visit::Subtree(left));
+                    }
+                    self.visits.push_back(Visit::Entry(node));
+                    if let Some(right) = S::node(self.store, &node.right) {
+                        self.visits.push_back(Visit::Subtree(right));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> ExactSizeIterator
+    for Iter<'_, K, P, V, M, KC, PC, S>
+{
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> FusedIterator for Iter<'_, K, P, V, M, KC, PC, S> {}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> Clone for Iter<'_, K, P, V, M, KC, PC, S> {
+    fn clone(&self) -> Self {
+        let visits = self
+            .visits
+            .iter()
+            .map(|visit| match visit {
+                Visit::Subtree(node) => Visit::Subtree(*node),
+                Visit::Entry(node) => Visit::Entry(*node),
+            })
+            .collect();
+        Iter {
+            store: self.store,
+            visits,
+            remaining: self.remaining,
+        }
+    }
+}
+
+type Node<K, P, V, KC, PC, S> = TreapNode<K, P, V, (), (), KC, PC, S>;
+
+enum VisitMut<'a, K: 'a, P: 'a, V: 'a, KC: 'a, PC: 'a, S: Storage> {
+    Subtree(<S as Sealed>::NodeRef<'a, Node<K, P, V, KC, PC, S>>),
+    Entry(&'a K, &'a mut V),
+}
+
+/// In-order iterator with mutable access to the values, created by `Treap::iter_mut`.
+///
+/// Nodes are opened from `nodes` as the walk reaches them, which hands out
+/// each one's fields at most once.
+pub struct IterMut<'a, K: 'a, P: 'a, V: 'a, KC: 'a = Natural, PC: 'a = Natural, S: Storage = Boxed>
+{
+    nodes: <S as Sealed>::Nodes<'a, Node<K, P, V, KC, PC, S>>,
+    visits: VecDeque<VisitMut<'a, K, P, V, KC, PC, S>>,
+    remaining: usize,
+}
+
+impl<'a, K, P, V, KC, PC, S: Storage> IterMut<'a, K, P, V, KC, PC, S> {
+    pub(super) fn new(treap: &'a mut TreapNodePtr<K, P, V, (), (), KC, PC, S>) -> Self {
+        let remaining = treap.len();
+        let TreapNodePtr { root, store } = treap;
+        let mut visits = VecDeque::new();
+        visits.extend(S::node_ref(root).map(VisitMut::Subtree));
+        IterMut {
+            nodes: S::nodes(store),
+            visits,
+            remaining,
+        }
+    }
+
+    pub(super) fn new_range<Q>(
+        treap: &'a mut TreapNodePtr<K, P, V, (), (), KC, PC, S>,
+        lo: Bound<&Q>,
+        hi: Bound<&Q>,
+    ) -> Self
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<K> + Compare<Q>,
+        PC: Compare<P>,
+    {
+        let remaining = treap.aggregate_bounded(lo, hi).1;
+        let TreapNodePtr { root, store } = treap;
+        let mut iter = IterMut {
+            nodes: S::nodes(store),
+            visits: VecDeque::new(),
+            remaining,
+        };
+        iter.push_range(root, lo, hi);
+        iter
+    }
+
+    fn open(
+        &mut self,
+        link: &'a mut Link<K, P, V, (), (), KC, PC, S>,
+    ) -> Option<&'a mut Node<K, P, V, KC, PC, S>> {
+        S::node_ref(link).map(|node| S::open(&mut self.nodes, node))
+    }
+
+    /// Same walk as `Iter::push_range`, splitting each node's borrow into
+    /// its fields.
+    fn push_range<Q>(
+        &mut self,
+        root: &'a mut Link<K, P, V, (), (), KC, PC, S>,
+        lo: Bound<&Q>,
+        hi: Bound<&Q>,
+    ) where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        let mut curr = root;
+        let split = loop {
+            let node = match self.open(curr) {
+                None => return,
+                Some(node) => node,
+            };
+            if !after_start::<KC, _, _>(&node.key, lo) {
+                curr = &mut node.right;
+            } else if !before_end::<KC, _, _>(&node.key, hi) {
+                curr = &mut node.left;
+            } else {
+                break node;
+            }
+        };
+        let TreapNode {
+            key,
+            value,
+            left,
+            right,
+            ..
+        } = split;
+
+        let mut lower = Vec::new();
+        let mut curr = left;
+        if let Bound::Unbounded = lo {
+            lower.extend(S::node_ref(curr).map(VisitMut::Subtree));
+        } else {
+            while let Some(node) = self.open(curr) {
+                let TreapNode {
+                    key,
+                    value,
+                    left,
+                    right,
+                    ..
+                } = node;
+                if after_start::<KC, _, _>(key, lo) {
+                    lower.extend(S::node_ref(right).map(VisitMut::Subtree));
+                    lower.push(VisitMut::Entry(key, value));
+                    curr = left;
+                } else {
+                    curr = right;
+                }
+            }
+        }
+        self.visits.extend(lower.into_iter().rev());
+        self.visits.push_back(VisitMut::Entry(key, value));
+
+        let mut curr = right;
+        if let Bound::Unbounded = hi {
+            self.visits.extend(S::node_ref(curr).map(VisitMut::Subtree));
+            return;
+        }
+        while let Some(node) = self.open(curr) {
+            let TreapNode {
+                key,
+                value,
+                left,
+                right,
+                ..
+            } = node;
+            if before_end::<KC, _, _>(key, hi) {
+                self.visits.extend(S::node_ref(left).map(VisitMut::Subtree));
+                self.visits.push_back(VisitMut::Entry(key, value));
+                curr = right;
+            } else {
+                curr = left;
+            }
+        }
+    }
+}
+
+impl<'a, K, P, V, KC, PC, S: Storage> Iterator for IterMut<'a, K, P, V, KC, PC, S> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.visits.pop_front()? {
+                VisitMut::Entry(key, value) => {
+                    self.remaining -= 1;
+                    return Some((key, value));
+                }
+                VisitMut::Subtree(node) => {
+                    let TreapNode {
+                        key,
+                        value,
+                        left,
+                        right,
+                        ..
+                    } = S::open(&mut self.nodes, node);
+                    if let Some(right) = S::node_ref(right) {
+                        self.visits.push_front(VisitMut::Subtree(right));
+                    }
+                    self.visits.push_front(VisitMut::Entry(key, value));
+                    if let Some(left) = S::node_ref(left) {
+                        self.visits.push_front(VisitMut::Subtree(left));
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, P, V, KC, PC, S: Storage> DoubleEndedIterator for IterMut<'_, K, P, V, KC, PC, S> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.visits.pop_back()? {
+                VisitMut::Entry(key, value) => {
+                    self.remaining -= 1;
+                    return Some((key, value));
+                }
+                VisitMut::Subtree(node) => {
+                    let TreapNode {
+                        key,
+                        value,
+                        left,
+                        right,
+                        ..
+                    } = S::open(&mut self.nodes, node);
+                    if let Some(left) = S::node_ref(left) {
+                        self.visits.push_back(VisitMut::Subtree(left));
+                    }
+                    self.visits.push_back(VisitMut::Entry(key, value));
+                    if let Some(right) = S::node_ref(right) {
+                        self.visits.push_back(VisitMut::Subtree(right));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<K, P, V, KC, PC, S: Storage> ExactSizeIterator for IterMut<'_, K, P, V, KC, PC, S> {}
+
+impl<K, P, V, KC, PC, S: Storage> FusedIterator for IterMut<'_, K, P, V, KC, PC, S> {}
+
+/// In-order iterator over the keys of a treap, created by `Treap::keys`.
+#[derive(Clone)]
+pub struct Keys<'a, K, P, V, M: Monoid<V>, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    pub(super) inner: Iter<'a, K, P, V, M, KC, PC, S>,
+}
+
+impl<'a, K, P, V, M: Monoid<V>, KC, PC, S: Storage> Iterator for Keys<'a, K, P, V, M, KC, PC, S> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> DoubleEndedIterator
+    for Keys<'_, K, P, V, M, KC, PC, S>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(k, _)| k)
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> ExactSizeIterator
+    for Keys<'_, K, P, V, M, KC, PC, S>
+{
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> FusedIterator for Keys<'_, K, P, V, M, KC, PC, S> {}
+
+/// In-order iterator over the values of a treap, created by `Treap::values`.
+#[derive(Clone)]
+pub struct Values<'a, K, P, V, M: Monoid<V>, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    pub(super) inner: Iter<'a, K, P, V, M, KC, PC, S>,
+}
+
+impl<'a, K, P, V, M: Monoid<V>, KC, PC, S: Storage> Iterator for Values<'a, K, P, V, M, KC, PC, S> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> DoubleEndedIterator
+    for Values<'_, K, P, V, M, KC, PC, S>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> ExactSizeIterator
+    for Values<'_, K, P, V, M, KC, PC, S>
+{
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> FusedIterator
+    for Values<'_, K, P, V, M, KC, PC, S>
+{
+}
+
+/// In-order iterator over mutable values, created by `Treap::values_mut`.
+pub struct ValuesMut<'a, K, P, V, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    pub(super) inner: IterMut<'a, K, P, V, KC, PC, S>,
+}
+
+impl<'a, K, P, V, KC, PC, S: Storage> Iterator for ValuesMut<'a, K, P, V, KC, PC, S> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, P, V, KC, PC, S: Storage> DoubleEndedIterator for ValuesMut<'_, K, P, V, KC, PC, S> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<K, P, V, KC, PC, S: Storage> ExactSizeIterator for ValuesMut<'_, K, P, V, KC, PC, S> {}
+
+impl<K, P, V, KC, PC, S: Storage> FusedIterator for ValuesMut<'_, K, P, V, KC, PC, S> {}
+
+/// In-order iterator over the entries within a key range, created by `Treap::range`.
+#[derive(Clone)]
+pub struct Range<'a, K, P, V, M: Monoid<V>, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    pub(super) inner: Iter<'a, K, P, V, M, KC, PC, S>,
+}
+
+impl<'a, K, P, V, M: Monoid<V>, KC, PC, S: Storage> Iterator for Range<'a, K, P, V, M, KC, PC, S> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> DoubleEndedIterator
+    for Range<'_, K, P, V, M, KC, PC, S>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> ExactSizeIterator
+    for Range<'_, K, P, V, M, KC, PC, S>
+{
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC, S: Storage> FusedIterator for Range<'_, K, P, V, M, KC, PC, S> {}
+
+/// Mutable in-order iterator over a key range, created by `Treap::range_mut`.
+pub struct RangeMut<'a, K, P, V, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    pub(super) inner: IterMut<'a, K, P, V, KC, PC, S>,
+}
+
+impl<'a, K, P, V, KC, PC, S: Storage> Iterator for RangeMut<'a, K, P, V, KC, PC, S> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, P, V, KC, PC, S: Storage> DoubleEndedIterator for RangeMut<'_, K, P, V, KC, PC, S> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl<K, P, V, KC, PC, S: Storage> ExactSizeIterator for RangeMut<'_, K, P, V, KC, PC, S> {}
+
+impl<K, P, V, KC, PC, S: Storage> FusedIterator for RangeMut<'_, K, P, V, KC, PC, S> {}
+
+impl<'a, K, P, V, M: Monoid<V>, KC, PC, S: Storage> Visit<'a, K, P, V, M, KC, PC, S> {
+    fn node(&self) -> &'a TreapNode<K, P, V, M, (), KC, PC, S> {
+        match self {
+            Visit::Subtree(node) | Visit::Entry(node) => node,
+        }
+    }
+}
+
+/// A `Visit` ordered by the priority of its top node, which is the highest
+/// priority it contains.
+struct ByPriority<'a, K, P, V, M: Monoid<V>, KC, PC, S: Storage>(Visit<'a, K, P, V, M, KC, PC, S>);
+
+impl<K, P, V, M: Monoid<V>, KC, PC: Compare<P>, S: Storage> PartialEq
+    for ByPriority<'_, K, P, V, M, KC, PC, S>
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC: Compare<P>, S: Storage> Eq
+    for ByPriority<'_, K, P, V, M, KC, PC, S>
+{
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC: Compare<P>, S: Storage> PartialOrd
+    for ByPriority<'_, K, P, V, M, KC, PC, S>
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC: Compare<P>, S: Storage> Ord
+    for ByPriority<'_, K, P, V, M, KC, PC, S>
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        PC::compare(&self.0.node().priority, &other.0.node().priority)
+    }
+}
+
+/// Entries within a key range by decreasing priority, created by
+/// `Treap::top_k_in_range`.
+///
+/// Keeps a heap of the pieces of the range that have not been visited yet, so
+/// each step costs O(log n) and nothing outside the range is touched.
+pub struct TopKInRange<'a, K, P, V, M: Monoid<V>, KC = Natural, PC = Natural, S: Storage = Boxed> {
+    store: &'a Store<K, P, V, M, (), KC, PC, S>,
+    heap: BinaryHeap<ByPriority<'a, K, P, V, M, KC, PC, S>>,
+    remaining: usize,
+}
+
+impl<'a, K, P, V, M: Monoid<V>, KC, PC: Compare<P>, S: Storage>
+    TopKInRange<'a, K, P, V, M, KC, PC, S>
+{
+    pub(super) fn new<Q>(
+        treap: &'a TreapNodePtr<K, P, V, M, (), KC, PC, S>,
+        lo: Bound<&Q>,
+        hi: Bound<&Q>,
+        k: usize,
+    ) -> Self
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        KC: Compare<Q>,
+    {
+        let range = Iter::new_range(treap, lo, hi);
+        TopKInRange {
+            store: range.store,
+            remaining: k.min(range.remaining),
+            heap: range.visits.into_iter().map(ByPriority).collect(),
+        }
+    }
+}
+
+impl<'a, K, P, V, M: Monoid<V>, KC, PC: Compare<P>, S: Storage> Iterator
+    for TopKInRange<'a, K, P, V, M, KC, PC, S>
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let visit = self.heap.pop()?.0;
+        let node = visit.node();
+        if let Visit::Subtree(_) = visit {
+            for child in [&node.left, &node.right] {
+                if let Some(child) = S::node(self.store, child) {
+                    self.heap.push(ByPriority(Visit::Subtree(child)));
+                }
+            }
+        }
+        Some((&node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC: Compare<P>, S: Storage> ExactSizeIterator
+    for TopKInRange<'_, K, P, V, M, KC, PC, S>
+{
+}
+
+impl<K, P, V, M: Monoid<V>, KC, PC: Compare<P>, S: Storage> FusedIterator
+    for TopKInRange<'_, K, P, V, M, KC, PC, S>
+{
+}
@@ -0,0 +1,113 @@
+use crate::compare::Compare;
+use crate::monoid::{Monoid, Update};
+use crate::treap::storage::Storage;
+use crate::treap::{Link, TreapNode, TreapNodePtr};
+use std::cmp::Ordering;
+
+/// Work left for `aggregate_at`, kept on an explicit stack.
+enum Step<'a, K, P, V, M: Monoid<V>, U: Update<V, M>, KC, PC, S: Storage> {
+    /// Sum positions `lo..hi` of a subtree.
+    Visit(&'a Link<K, P, V, M, U, KC, PC, S>, usize, usize),
+    /// Join the two latest sums, with the node's value between them if the
+    /// range covers it.
+    Join(&'a TreapNode<K, P, V, M, U, KC, PC, S>, bool),
+}
+
+/// Access by position for the implicit treap, which may reverse subtrees. A
+/// reversal swaps the children of the node it reaches, so below a node with a
+/// pending reversal the positions read mirrored.
+impl<K, P, V, M: Monoid<V>, U: Update<V, M>, KC: Compare<K>, PC: Compare<P>, S: Storage>
+    TreapNodePtr<K, P, V, M, U, KC, PC, S>
+{
+    /// Value at position `idx`. Nothing is pushed down, so the value misses
+    /// pending updates other than reversals.
+    pub(crate) fn value_at(&self, mut idx: usize) -> Option<&V> {
+        if idx >= self.len() {
+            return None;
+        }
+        let mut curr = &self.root;
+        while let Some(node) = S::node(&self.store, curr) {
+            let left_len = self.len_in(&node.left);
+            let child = match idx.cmp(&left_len) {
+                Ordering::Less => &node.left,
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Greater => {
+                    idx -= left_len + 1;
+                    &node.right
+                }
+            };
+            if node.reverses_below() {
+                idx = self.len_in(child) - 1 - idx;
+            }
+            curr = child;
+        }
+        None
+    }
+
+    /// Value at position `idx`, with pending updates pushed down its path.
+    ///
+    /// Summaries are not recomputed, so writing through the reference is only
+    /// sound without a summary.
+    pub(crate) fn value_at_mut(&mut self, mut idx: usize) -> Option<&mut V> {
+        S::descend_mut(&mut self.store, &mut self.root, |store, node| {
+            Self::push_down(store, node);
+            let left_len = Self::len_of(store, &S::get(store, node).left);
+            let ord = idx.cmp(&left_len);
+            if ord.is_gt() {
+                idx -= left_len + 1;
+            }
+            ord
+        })
+        .map(|node| &mut node.value)
+    }
+
+    /// Combines the summaries of the values at positions `lo..hi`.
+    pub(crate) fn aggregate_at(&self, lo: usize, hi: usize) -> M {
+        let mut steps = vec![Step::Visit(&self.root, lo, hi)];
+        // (summary, number of values it covers)
+        let mut done: Vec<(M, usize)> = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(link, lo, hi) => {
+                    let node = match S::node(&self.store, link) {
+                        Some(node) if lo < hi => node,
+                        _ => {
+                            done.push((M::identity(), 0));
+                            continue;
+                        }
+                    };
+                    if lo == 0 && hi == node.size {
+                        done.push((node.summary.clone(), node.size));
+                        continue;
+                    }
+                    let (left_len, right_len) = (self.len_in(&node.left), self.len_in(&node.right));
+                    let mut left = (lo.min(left_len), hi.min(left_len));
+                    let mut right = (
+                        lo.saturating_sub(left_len + 1),
+                        hi.saturating_sub(left_len + 1),
+                    );
+                    if node.reverses_below() {
+                        left = (left_len - left.1, left_len - left.0);
+                        right = (right_len - right.1, right_len - right.0);
+                    }
+                    steps.push(Step::Join(node, lo <= left_len && left_len < hi));
+                    steps.push(Step::Visit(&node.right, right.0, right.1));
+                    steps.push(Step::Visit(&node.left, left.0, left.1));
+                }
+                Step::Join(node, with_value) => {
+                    let (right, right_count) = node.pending(done.pop().unwrap());
+                    let (left, left_count) = node.pending(done.pop().unwrap());
+                    let mut summary = left;
+                    if with_value {
+                        summary = summary.combine(&M::from_value(&node.value));
+                    }
+                    done.push((
+                        summary.combine(&right),
+                        left_count + usize::from(with_value) + right_count,
+                    ));
+                }
+            }
+        }
+        done.pop().unwrap().0
+    }
+}
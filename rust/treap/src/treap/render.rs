@@ -0,0 +1,150 @@
+use crate::monoid::{Monoid, Update};
+use crate::treap::storage::Storage;
+use crate::treap::{TreapNode, TreapNodePtr};
+use std::fmt::{self, Debug, Display, Write};
+
+/// What a node holds besides its key, priority and value: the summary of its
+/// subtree, the tag not yet pushed to its children and whether they still
+/// have to be mirrored. A zero-sized summary like `()` can only take one
+/// value, so it is left out.
+fn annotations<K, P, V, M, U, KC, PC, S>(node: &TreapNode<K, P, V, M, U, KC, PC, S>) -> Vec<String>
+where
+    M: Monoid<V> + Debug,
+    U: Update<V, M> + Debug,
+    S: Storage,
+{
+    let mut annotations = Vec::new();
+    if std::mem::size_of::<M>() > 0 {
+        annotations.push(format!("sum={:?}", node.summary));
+    }
+    if let Some(tag) = &node.lazy {
+        annotations.push(format!("tag={tag:?}"));
+    }
+    if node.reversed {
+        annotations.push("reversed".to_string());
+    }
+    annotations
+}
+
+/// Debug dumps of the tree shape. Both walk the tree with an explicit stack,
+/// so a degenerate treap renders without overflowing. Pending tags are shown
+/// where they sit, so values below a tagged node may be stale.
+impl<
+        K: Debug,
+        P: Debug,
+        V: Debug,
+        M: Monoid<V> + Debug,
+        U: Update<V, M> + Debug,
+        KC,
+        PC,
+        S: Storage,
+    > TreapNodePtr<K, P, V, M, U, KC, PC, S>
+{
+    /// Renders the tree in Graphviz DOT format, one box per node showing its
+    /// key, priority, value and annotations. Edges are labelled `L` and `R`.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph treap {\n    node [shape=box];\n");
+        let mut next_id = 0;
+        // (node, parent id and side of the edge leading to it)
+        let mut stack: Vec<_> = S::node(&self.store, &self.root)
+            .map(|node| (node, None))
+            .into_iter()
+            .collect();
+        while let Some((node, parent)) = stack.pop() {
+            let id = next_id;
+            next_id += 1;
+            let mut label = format!("{:?}\np={:?}\n{:?}", node.key, node.priority, node.value);
+            for annotation in annotations(node) {
+                let _ = write!(label, "\n{annotation}");
+            }
+            let _ = writeln!(dot, "    n{id} [label=\"{}\"];", escape(&label));
+            if let Some((parent, side)) = parent {
+                let _ = writeln!(dot, "    n{parent} -> n{id} [label=\"{side}\"];");
+            }
+            if let Some(right) = S::node(&self.store, &node.right) {
+                stack.push((right, Some((id, "R"))));
+            }
+            if let Some(left) = S::node(&self.store, &node.left) {
+                stack.push((left, Some((id, "L"))));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Draws the tree sideways, root first, one `key (p=priority): value` line
+/// per node, with any annotations after the priority. Children are marked `L`
+/// or `R`, since a lone child could be either.
+impl<
+        K: Debug,
+        P: Debug,
+        V: Debug,
+        M: Monoid<V> + Debug,
+        U: Update<V, M> + Debug,
+        KC,
+        PC,
+        S: Storage,
+    > Display for TreapNodePtr<K, P, V, M, U, KC, PC, S>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // (node, indent inherited from its ancestors, branch leading to it)
+        let mut stack: Vec<_> = S::node(&self.store, &self.root)
+            .map(|node| (node, String::new(), ""))
+            .into_iter()
+            .collect();
+        if stack.is_empty() {
+            return writeln!(f, "(empty)");
+        }
+        while let Some((node, indent, branch)) = stack.pop() {
+            write!(f, "{indent}{branch}{:?} (p={:?}", node.key, node.priority)?;
+            for annotation in annotations(node) {
+                write!(f, ", {annotation}")?;
+            }
+            writeln!(f, "): {:?}", node.value)?;
+            let indent = match branch {
+                "" => indent,
+                b if b.starts_with('└') => indent + "    ",
+                _ => indent + "│   ",
+            };
+            match (
+                S::node(&self.store, &node.left),
+                S::node(&self.store, &node.right),
+            ) {
+                (Some(left), Some(right)) => {
+                    stack.push((right, indent.clone(), "└── R "));
+                    stack.push((left, indent, "├── L "));
+                }
+                (Some(left), None) => stack.push((left, indent, "└── L ")),
+                (None, Some(right)) => stack.push((right, indent, "└── R ")),
+                (None, None) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escapes a label for use inside a double-quoted DOT string.
+pub(crate) fn escape(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("say \"hi\"\n\\"), "say \\\"hi\\\"\\n\\\\");
+    }
+}
@@ -0,0 +1,131 @@
+use std::cmp::Ordering;
+
+/// Where a treap keeps its nodes: each in a `Box` of its own with [`Boxed`],
+/// or all in one `Vec` with [`Arena`](crate::arena_treap::Arena).
+///
+/// The tree algorithms are written once against this trait, which can't be
+/// implemented outside the crate.
+pub trait Storage: Sealed {}
+
+/// Every node in a `Box` of its own; the storage of a [`Treap`](super::Treap).
+pub struct Boxed;
+
+impl Storage for Boxed {}
+
+/// The node handling behind [`Storage`], out of reach of other crates.
+pub trait Sealed: Sized + 'static {
+    /// Owning link to a node.
+    type Handle<N>;
+    /// Per-treap state holding the nodes, if the handles don't.
+    type Store<N>: Default;
+    /// Node not yet opened from `Nodes`.
+    type NodeRef<'a, N: 'a>;
+    /// Mutable access to many nodes at once, for `IterMut`.
+    type Nodes<'a, N: 'a>;
+
+    /// Whether the nodes live in the store rather than behind their handles.
+    /// If so, dropping the store frees them all, and moving nodes to another
+    /// treap means moving them to its store.
+    const STORE_OWNS_NODES: bool;
+
+    fn alloc<N>(store: &mut Self::Store<N>, node: N) -> Self::Handle<N>;
+
+    fn free<N>(store: &mut Self::Store<N>, handle: Self::Handle<N>) -> N;
+
+    /// Replaces the node with `f` of it, keeping its place in the store.
+    fn map<N, F: FnOnce(N) -> N>(
+        store: &mut Self::Store<N>,
+        handle: Self::Handle<N>,
+        f: F,
+    ) -> Self::Handle<N>;
+
+    fn get<'a, N>(store: &'a Self::Store<N>, handle: &'a Self::Handle<N>) -> &'a N;
+
+    fn get_mut<'a, N>(store: &'a mut Self::Store<N>, handle: &'a mut Self::Handle<N>) -> &'a mut N;
+
+    fn node<'a, N>(store: &'a Self::Store<N>, link: &'a Option<Self::Handle<N>>) -> Option<&'a N> {
+        link.as_ref().map(|handle| Self::get(store, handle))
+    }
+
+    /// Walks down from `link`, asking `step` at each node whether to go to
+    /// the left child (`Less`), the right child (`Greater`) or stop there.
+    fn descend_mut<'a, N: Linked<Self>, F>(
+        store: &'a mut Self::Store<N>,
+        link: &'a mut Option<Self::Handle<N>>,
+        step: F,
+    ) -> Option<&'a mut N>
+    where
+        F: FnMut(&mut Self::Store<N>, &mut Self::Handle<N>) -> Ordering;
+
+    fn nodes<N>(store: &mut Self::Store<N>) -> Self::Nodes<'_, N>;
+
+    fn node_ref<N>(link: &mut Option<Self::Handle<N>>) -> Option<Self::NodeRef<'_, N>>;
+
+    /// Panics if `node` was opened before.
+    fn open<'a, N: 'a>(nodes: &mut Self::Nodes<'a, N>, node: Self::NodeRef<'a, N>) -> &'a mut N;
+}
+
+/// A node with two child links.
+pub trait Linked<S: Sealed>: Sized {
+    fn child(&self, left: bool) -> &Option<S::Handle<Self>>;
+
+    fn child_mut(&mut self, left: bool) -> &mut Option<S::Handle<Self>>;
+}
+
+impl Sealed for Boxed {
+    type Handle<N> = Box<N>;
+    type Store<N> = ();
+    type NodeRef<'a, N: 'a> = &'a mut N;
+    type Nodes<'a, N: 'a> = ();
+
+    const STORE_OWNS_NODES: bool = false;
+
+    fn alloc<N>(_: &mut (), node: N) -> Box<N> {
+        Box::new(node)
+    }
+
+    fn free<N>(_: &mut (), handle: Box<N>) -> N {
+        *handle
+    }
+
+    fn map<N, F: FnOnce(N) -> N>(_: &mut (), mut handle: Box<N>, f: F) -> Box<N> {
+        let node = *handle;
+        *handle = f(node);
+        handle
+    }
+
+    fn get<'a, N>(_: &'a (), handle: &'a Box<N>) -> &'a N {
+        handle
+    }
+
+    fn get_mut<'a, N>(_: &'a mut (), handle: &'a mut Box<N>) -> &'a mut N {
+        handle
+    }
+
+    fn descend_mut<'a, N: Linked<Self>, F>(
+        store: &'a mut (),
+        mut link: &'a mut Option<Box<N>>,
+        mut step: F,
+    ) -> Option<&'a mut N>
+    where
+        F: FnMut(&mut (), &mut Box<N>) -> Ordering,
+    {
+        loop {
+            let handle = link.as_mut()?;
+            match step(store, handle) {
+                Ordering::Equal => return Some(handle),
+                ord => link = handle.child_mut(ord.is_lt()),
+            }
+        }
+    }
+
+    fn nodes<N>(_: &mut ()) {}
+
+    fn node_ref<N>(link: &mut Option<Box<N>>) -> Option<&mut N> {
+        link.as_deref_mut()
+    }
+
+    fn open<'a, N: 'a>(_: &mut (), node: &'a mut N) -> &'a mut N {
+        node
+    }
+}
@@ -0,0 +1,659 @@
+use crate::rng::XorShift64;
+use crate::treap;
+use crate::treap::{
+    Drain, ExtractIf, Iter, IterMut, Keys, Range, RangeMut, Treap, TreapError, Values, ValuesMut,
+};
+use std::borrow::Borrow;
+use std::ops::RangeBounds;
+
+/// Ordered map backed by a [`Treap`] that draws its own random priorities.
+///
+/// The expected depth is O(log n) for any insertion order, so it can be used
+/// wherever a `BTreeMap` would be.
+pub struct TreapMap<K: Ord, V> {
+    treap: Treap<K, u64, V>,
+    rng: XorShift64,
+}
+
+impl<K: Ord, V> TreapMap<K, V> {
+    pub fn new() -> Self {
+        TreapMap {
+            treap: Treap::default(),
+            rng: XorShift64::from_entropy(),
+        }
+    }
+
+    /// Creates a map whose priorities are reproducible across runs.
+    pub fn with_seed(seed: u64) -> Self {
+        TreapMap {
+            treap: Treap::default(),
+            rng: XorShift64::new(seed),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let priority = self.rng.next_u64();
+        self.treap.insert(key, priority, value).map(|(_, v)| v)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.treap.erase(key).map(|(_, v)| v)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.treap.get(key)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.treap.get_mut(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.treap.contains(key)
+    }
+
+    /// Moves all entries with keys `>= key` into a new map.
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        TreapMap {
+            treap: self.treap.split_off(key),
+            rng: XorShift64::new(self.rng.next_u64()),
+        }
+    }
+
+    /// Moves all entries of `other` to the end of `self`; see `Treap::append`.
+    pub fn append(&mut self, other: &mut Self) -> Result<(), TreapError> {
+        self.treap.append(&mut other.treap)
+    }
+
+    /// Moves all entries with keys in `range` into a new map.
+    pub fn split_range<Q, R>(&mut self, range: R) -> Self
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+        R: RangeBounds<Q>,
+    {
+        TreapMap {
+            treap: self.treap.split_range(range),
+            rng: XorShift64::new(self.rng.next_u64()),
+        }
+    }
+
+    /// Keeps only the entries for which `f` returns true; see `Treap::retain`.
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.treap.retain(f)
+    }
+
+    /// Removes and yields the entries for which `pred` returns true; see
+    /// `Treap::extract_if`.
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, K, u64, V, (), (), F>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.treap.extract_if(pred)
+    }
+
+    /// Removes the entries with keys in `range` and yields them in key order.
+    pub fn drain<Q, R>(&mut self, range: R) -> Drain<K, u64, V, (), ()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+        R: RangeBounds<Q>,
+    {
+        self.treap.drain(range)
+    }
+
+    /// Merges `other` into `self`; see `Treap::union`.
+    pub fn union<F>(self, other: Self, resolve: F) -> Self
+    where
+        F: FnMut(&K, V, V) -> V,
+    {
+        TreapMap {
+            treap: self.treap.union(other.treap, resolve),
+            rng: self.rng,
+        }
+    }
+
+    pub fn intersection(self, other: Self) -> Self {
+        TreapMap {
+            treap: self.treap.intersection(other.treap),
+            rng: self.rng,
+        }
+    }
+
+    pub fn difference(self, other: Self) -> Self {
+        TreapMap {
+            treap: self.treap.difference(other.treap),
+            rng: self.rng,
+        }
+    }
+
+    pub fn symmetric_difference(self, other: Self) -> Self {
+        TreapMap {
+            treap: self.treap.symmetric_difference(other.treap),
+            rng: self.rng,
+        }
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        match self.treap.entry(key) {
+            treap::Entry::Vacant(inner) => Entry::Vacant(VacantEntry {
+                inner,
+                rng: &mut self.rng,
+            }),
+            treap::Entry::Occupied(inner) => Entry::Occupied(OccupiedEntry { inner }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.treap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.treap.is_empty()
+    }
+
+    /// Returns the entry with the `i`-th smallest key (0-indexed).
+    pub fn nth(&self, i: usize) -> Option<(&K, &V)> {
+        self.treap.nth(i)
+    }
+
+    /// Returns the number of keys strictly smaller than `key`.
+    pub fn rank<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.treap.rank(key)
+    }
+
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        self.treap.first_key_value()
+    }
+
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        self.treap.last_key_value()
+    }
+
+    /// Returns the entry with the largest key `<= key`.
+    pub fn floor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.treap.floor(key)
+    }
+
+    /// Returns the entry with the smallest key `>= key`.
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.treap.ceiling(key)
+    }
+
+    /// Returns the entry with the largest key `< key`.
+    pub fn predecessor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.treap.predecessor(key)
+    }
+
+    /// Returns the entry with the smallest key `> key`.
+    pub fn successor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.treap.successor(key)
+    }
+
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        self.treap.pop_first()
+    }
+
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        self.treap.pop_last()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, u64, V, ()> {
+        self.treap.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, u64, V> {
+        self.treap.iter_mut()
+    }
+
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, u64, V, ()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+        R: RangeBounds<Q>,
+    {
+        self.treap.range(range)
+    }
+
+    pub fn range_mut<Q, R>(&mut self, range: R) -> RangeMut<'_, K, u64, V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+        R: RangeBounds<Q>,
+    {
+        self.treap.range_mut(range)
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, u64, V, ()> {
+        self.treap.keys()
+    }
+
+    pub fn values(&self) -> Values<'_, K, u64, V, ()> {
+        self.treap.values()
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, u64, V> {
+        self.treap.values_mut()
+    }
+
+    pub fn into_vec(self) -> Vec<(K, V)> {
+        self.treap.into_vec()
+    }
+}
+
+impl<K: Ord, V> Default for TreapMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// View into a single key of a [`TreapMap`], created by `TreapMap::entry`.
+pub enum Entry<'a, K: Ord, V> {
+    Vacant(VacantEntry<'a, K, V>),
+    Occupied(OccupiedEntry<'a, K, V>),
+}
+
+pub struct VacantEntry<'a, K: Ord, V> {
+    inner: treap::VacantEntry<'a, K, u64, V>,
+    rng: &'a mut XorShift64,
+}
+
+pub struct OccupiedEntry<'a, K: Ord, V> {
+    inner: treap::OccupiedEntry<'a, K, u64, V>,
+}
+
+impl<'a, K: Ord, V> Entry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Vacant(entry) => entry.key(),
+            Entry::Occupied(entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Vacant(entry) => entry.insert(default),
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Vacant(entry) => entry.insert(default()),
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        self.inner.key()
+    }
+
+    pub fn into_key(self) -> K {
+        self.inner.into_key()
+    }
+
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.inner.insert(self.rng.next_u64(), value)
+    }
+}
+
+impl<'a, K: Ord, V> OccupiedEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        self.inner.key()
+    }
+
+    pub fn get(&self) -> &V {
+        self.inner.get()
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.inner.get_mut()
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.inner.into_mut()
+    }
+
+    pub fn insert(&mut self, value: V) -> V {
+        self.inner.insert(value)
+    }
+
+    pub fn remove(self) -> V {
+        self.inner.remove()
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        self.inner.remove_entry()
+    }
+}
+
+/// Ordered set backed by a [`TreapMap`] with unit values.
+pub struct TreapSet<K: Ord> {
+    map: TreapMap<K, ()>,
+}
+
+impl<K: Ord> TreapSet<K> {
+    pub fn new() -> Self {
+        TreapSet {
+            map: TreapMap::new(),
+        }
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        TreapSet {
+            map: TreapMap::with_seed(seed),
+        }
+    }
+
+    /// Returns true if the key was not already present.
+    pub fn insert(&mut self, key: K) -> bool {
+        self.map.insert(key, ()).is_none()
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.remove(key).is_some()
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        TreapSet {
+            map: self.map.union(other.map, |_, _, _| ()),
+        }
+    }
+
+    pub fn intersection(self, other: Self) -> Self {
+        TreapSet {
+            map: self.map.intersection(other.map),
+        }
+    }
+
+    pub fn difference(self, other: Self) -> Self {
+        TreapSet {
+            map: self.map.difference(other.map),
+        }
+    }
+
+    pub fn symmetric_difference(self, other: Self) -> Self {
+        TreapSet {
+            map: self.map.symmetric_difference(other.map),
+        }
+    }
+
+    pub fn first(&self) -> Option<&K> {
+        self.map.first_key_value().map(|(k, _)| k)
+    }
+
+    pub fn last(&self) -> Option<&K> {
+        self.map.last_key_value().map(|(k, _)| k)
+    }
+
+    /// Returns the largest key `<= key`.
+    pub fn floor<Q>(&self, key: &Q) -> Option<&K>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.floor(key).map(|(k, _)| k)
+    }
+
+    /// Returns the smallest key `>= key`.
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<&K>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.ceiling(key).map(|(k, _)| k)
+    }
+
+    /// Returns the largest key `< key`.
+    pub fn predecessor<Q>(&self, key: &Q) -> Option<&K>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.predecessor(key).map(|(k, _)| k)
+    }
+
+    /// Returns the smallest key `> key`.
+    pub fn successor<Q>(&self, key: &Q) -> Option<&K>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.successor(key).map(|(k, _)| k)
+    }
+
+    pub fn pop_first(&mut self) -> Option<K> {
+        self.map.pop_first().map(|(k, _)| k)
+    }
+
+    pub fn pop_last(&mut self) -> Option<K> {
+        self.map.pop_last().map(|(k, _)| k)
+    }
+
+    pub fn iter(&self) -> Keys<'_, K, u64, (), ()> {
+        self.map.keys()
+    }
+
+    pub fn into_vec(self) -> Vec<K> {
+        self.map.into_vec().into_iter().map(|(k, _)| k).collect()
+    }
+}
+
+impl<K: Ord> Default for TreapSet<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::treap_map::{Entry, TreapMap, TreapSet};
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+
+    #[test]
+    fn test_map() {
+        let mut map = TreapMap::with_seed(7);
+        assert!(map.is_empty());
+        assert_eq!(None, map.insert("k2", 2));
+        assert_eq!(None, map.insert("k1", 1));
+        assert_eq!(Some(2), map.insert("k2", 20));
+        assert_eq!(2, map.len());
+
+        assert_eq!(Some(&20), map.get(&"k2"));
+        *map.get_mut(&"k2").unwrap() += 1;
+        assert_eq!(Some(&21), map.get(&"k2"));
+        assert_eq!(None, map.get_mut(&"k3"));
+        assert_eq!(Some((&"k1", &1)), map.first_key_value());
+        assert_eq!(Some((&"k2", &21)), map.ceiling(&"k11"));
+        assert_eq!(None, map.predecessor(&"k1"));
+        assert_eq!(Some(1), map.remove(&"k1"));
+        assert_eq!(None, map.remove(&"k1"));
+        assert_eq!(None, map.get(&"k1"));
+        assert_eq!(1, map.len());
+    }
+
+    #[test]
+    fn test_against_btree_map() {
+        let mut map = TreapMap::with_seed(42);
+        let mut expected = BTreeMap::new();
+        // sequential keys would degenerate a treap with caller-chosen priorities
+        for i in 0..10000u64 {
+            assert_eq!(expected.insert(i, i * 2), map.insert(i, i * 2));
+        }
+        for i in (0..10000u64).step_by(3) {
+            assert_eq!(expected.remove(&i), map.remove(&i));
+        }
+        assert_eq!(expected.len(), map.len());
+        assert!(expected.iter().eq(map.iter()));
+        assert!(expected.range(500..600).eq(map.range(500..600)));
+
+        let mut upper = map.split_off(&5000);
+        let middle = map.split_range(1000..2000);
+        assert!(expected.range(1000..2000).eq(middle.iter()));
+        assert!(expected.range(5000..).eq(upper.iter()));
+        assert!(map.append(&mut upper).is_ok());
+        expected.retain(|k, _| !(1000..2000).contains(k));
+        map.retain(|k, _| k % 10 != 0);
+        expected.retain(|k, _| k % 10 != 0);
+        let drained: Vec<_> = expected.extract_if(7000..7100, |_, _| true).collect();
+        assert!(drained.into_iter().eq(map.drain(7000..7100)));
+        assert!(expected.into_iter().eq(map.into_vec()));
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut map = TreapMap::with_seed(3);
+        for word in "the quick brown fox jumps over the lazy dog the end".split(' ') {
+            *map.entry(word).or_default() += 1;
+        }
+        assert_eq!(Some(&3), map.get(&"the"));
+        assert_eq!(Some(&1), map.get(&"fox"));
+
+        if let Entry::Occupied(entry) = map.entry("fox") {
+            assert_eq!(("fox", 1), entry.remove_entry());
+        }
+        map.entry("fox").and_modify(|_| unreachable!()).or_insert(7);
+        assert_eq!(Some(&7), map.get(&"fox"));
+        assert_eq!(9, map.len());
+    }
+
+    #[test]
+    fn test_borrowed_lookups() {
+        let mut map: TreapMap<String, usize> = TreapMap::with_seed(9);
+        for word in ["apple", "banana", "cherry", "date"] {
+            map.insert(word.to_string(), word.len());
+        }
+        assert_eq!(Some(&6), map.get("banana"));
+        assert!(map.contains_key("date"));
+        assert_eq!(2, map.rank("cherry"));
+        let range: Vec<&str> = map
+            .range::<str, _>((Bound::Included("b"), Bound::Excluded("d")))
+            .map(|(k, _)| k.as_str())
+            .collect();
+        assert_eq!(vec!["banana", "cherry"], range);
+        assert_eq!(Some(5), map.remove("apple"));
+        assert_eq!(None, map.get("apple"));
+
+        let mut set: TreapSet<String> = TreapSet::new();
+        set.insert("x".to_string());
+        assert!(set.contains("x"));
+        assert!(set.remove("x"));
+    }
+
+    #[test]
+    fn test_set() {
+        let mut set = TreapSet::new();
+        assert!(set.insert(3));
+        assert!(set.insert(1));
+        assert!(!set.insert(3));
+        assert!(set.contains(&1));
+        assert!(set.remove(&1));
+        assert!(!set.contains(&1));
+        assert_eq!(vec![&3], set.iter().collect::<Vec<_>>());
+        set.insert(7);
+        assert_eq!(Some(&3), set.floor(&5));
+        assert_eq!(Some(&7), set.successor(&3));
+        assert_eq!(Some(7), set.pop_last());
+        assert_eq!(vec![3], set.into_vec());
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let set = |keys: &[i32]| {
+            let mut set = TreapSet::new();
+            keys.iter().for_each(|k| _ = set.insert(*k));
+            set
+        };
+        let (a, b) = (&[1, 2, 3, 4][..], &[3, 4, 5][..]);
+        assert_eq!(vec![1, 2, 3, 4, 5], set(a).union(set(b)).into_vec());
+        assert_eq!(vec![3, 4], set(a).intersection(set(b)).into_vec());
+        assert_eq!(vec![1, 2], set(a).difference(set(b)).into_vec());
+        assert_eq!(
+            vec![1, 2, 5],
+            set(a).symmetric_difference(set(b)).into_vec()
+        );
+    }
+}
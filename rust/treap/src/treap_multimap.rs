@@ -0,0 +1,404 @@
+use crate::treap;
+use crate::treap_map::TreapMap;
+use std::borrow::Borrow;
+use std::iter::FusedIterator;
+use std::ops::Bound;
+
+/// Ordered map that keeps every value inserted under a key, backed by a
+/// [`TreapMap`] from each distinct key to its values in insertion order.
+pub struct TreapMultiMap<K: Ord, V> {
+    map: TreapMap<K, Vec<V>>,
+    len: usize,
+}
+
+impl<K: Ord, V> TreapMultiMap<K, V> {
+    pub fn new() -> Self {
+        TreapMultiMap {
+            map: TreapMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Creates a map whose priorities are reproducible across runs.
+    pub fn with_seed(seed: u64) -> Self {
+        TreapMultiMap {
+            map: TreapMap::with_seed(seed),
+            len: 0,
+        }
+    }
+
+    /// Adds `value` after the values already stored under `key`.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.map.entry(key).or_default().push(value);
+        self.len += 1;
+    }
+
+    /// Returns the number of values stored under `key`.
+    pub fn count<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.get(key).map_or(0, Vec::len)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Returns the values stored under `key`, in insertion order.
+    pub fn equal_range<Q>(&self, key: &Q) -> &[V]
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    /// Removes the most recently inserted value under `key`.
+    pub fn remove_one<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        let values = self.map.get_mut(key)?;
+        let value = values.pop();
+        // only removing the last value of a key takes a second descent
+        if values.is_empty() {
+            self.map.remove(key);
+        }
+        self.len -= 1;
+        value
+    }
+
+    /// Removes all values under `key` and returns them in insertion order.
+    pub fn remove_all<Q>(&mut self, key: &Q) -> Vec<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        let values = self.map.remove(key).unwrap_or_default();
+        self.len -= values.len();
+        values
+    }
+
+    /// Returns the number of values, counting duplicates.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over all entries by key, and by insertion order within a key.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            keys: self.map.iter(),
+            current: None,
+            remaining: self.len,
+        }
+    }
+
+    /// Iterates over the distinct keys in order.
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys {
+            inner: self.map.keys(),
+        }
+    }
+}
+
+impl<K: Ord, V> Default for TreapMultiMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Iterator over the entries of a [`TreapMultiMap`], created by `TreapMultiMap::iter`.
+pub struct Iter<'a, K: Ord, V> {
+    keys: treap::Iter<'a, K, u64, Vec<V>, ()>,
+    current: Option<(&'a K, std::slice::Iter<'a, V>)>,
+    remaining: usize,
+}
+
+impl<'a, K: Ord, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, values)) = &mut self.current {
+                if let Some(value) = values.next() {
+                    self.remaining -= 1;
+                    return Some((key, value));
+                }
+            }
+            let (key, values) = self.keys.next()?;
+            self.current = Some((key, values.iter()));
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Ord, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+impl<K: Ord, V> FusedIterator for Iter<'_, K, V> {}
+
+/// Iterator over the distinct keys of a [`TreapMultiMap`], created by
+/// `TreapMultiMap::keys`.
+pub struct Keys<'a, K: Ord, V> {
+    inner: treap::Keys<'a, K, u64, Vec<V>, ()>,
+}
+
+impl<'a, K: Ord, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K: Ord, V> DoubleEndedIterator for Keys<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl<K: Ord, V> ExactSizeIterator for Keys<'_, K, V> {}
+
+impl<K: Ord, V> FusedIterator for Keys<'_, K, V> {}
+
+/// Ordered set that counts repeated keys, backed by a [`TreapMultiMap`] with
+/// unit values.
+pub struct TreapMultiSet<K: Ord> {
+    map: TreapMultiMap<K, ()>,
+}
+
+impl<K: Ord> TreapMultiSet<K> {
+    pub fn new() -> Self {
+        TreapMultiSet {
+            map: TreapMultiMap::new(),
+        }
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        TreapMultiSet {
+            map: TreapMultiMap::with_seed(seed),
+        }
+    }
+
+    pub fn insert(&mut self, key: K) {
+        self.map.insert(key, ());
+    }
+
+    pub fn count<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.count(key)
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Returns every stored copy of `key`.
+    pub fn equal_range<Q>(&self, key: &Q) -> EqualRange<'_, K>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        let bounds = (Bound::Included(key), Bound::Included(key));
+        match self.map.map.range::<Q, _>(bounds).next() {
+            Some((key, copies)) => EqualRange {
+                key: Some(key),
+                remaining: copies.len(),
+            },
+            None => EqualRange {
+                key: None,
+                remaining: 0,
+            },
+        }
+    }
+
+    /// Removes one copy of `key`; returns false if there was none.
+    pub fn remove_one<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.remove_one(key).is_some()
+    }
+
+    /// Removes all copies of `key` and returns how many there were.
+    pub fn remove_all<Q>(&mut self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        self.map.remove_all(key).len()
+    }
+
+    /// Returns the number of keys, counting duplicates.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterates over the keys in order, each repeated by its count.
+    pub fn iter(&self) -> SetIter<'_, K> {
+        SetIter {
+            inner: self.map.iter(),
+        }
+    }
+}
+
+impl<K: Ord> Default for TreapMultiSet<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Iterator over the keys of a [`TreapMultiSet`], created by `TreapMultiSet::iter`.
+pub struct SetIter<'a, K: Ord> {
+    inner: Iter<'a, K, ()>,
+}
+
+impl<'a, K: Ord> Iterator for SetIter<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K: Ord> ExactSizeIterator for SetIter<'_, K> {}
+
+impl<K: Ord> FusedIterator for SetIter<'_, K> {}
+
+/// Copies of one key in a [`TreapMultiSet`], created by `TreapMultiSet::equal_range`.
+pub struct EqualRange<'a, K> {
+    key: Option<&'a K>,
+    remaining: usize,
+}
+
+impl<'a, K> Iterator for EqualRange<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.key
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K> ExactSizeIterator for EqualRange<'_, K> {}
+
+impl<K> FusedIterator for EqualRange<'_, K> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::treap_multimap::{TreapMultiMap, TreapMultiSet};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_multimap() {
+        let mut log = TreapMultiMap::with_seed(5);
+        assert!(log.is_empty());
+        log.insert(20, "b");
+        log.insert(10, "a");
+        log.insert(20, "c");
+        log.insert(20, "d");
+        assert_eq!(4, log.len());
+        assert_eq!(3, log.count(&20));
+        assert_eq!(0, log.count(&30));
+        assert_eq!(&["b", "c", "d"], log.equal_range(&20));
+        assert!(log.equal_range(&30).is_empty());
+        assert_eq!(
+            vec![(&10, &"a"), (&20, &"b"), (&20, &"c"), (&20, &"d")],
+            log.iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(Some("d"), log.remove_one(&20));
+        assert_eq!(Some("a"), log.remove_one(&10));
+        assert_eq!(None, log.remove_one(&10));
+        assert!(!log.contains_key(&10));
+        assert_eq!(vec![&20], log.keys().collect::<Vec<_>>());
+        assert_eq!(vec!["b", "c"], log.remove_all(&20));
+        assert!(log.remove_all(&20).is_empty());
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn test_multimap_against_btree_map() {
+        let mut map = TreapMultiMap::with_seed(8);
+        let mut expected: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        for i in 0..3000u32 {
+            let key = (i * 7) % 101;
+            map.insert(key, i);
+            expected.entry(key).or_default().push(i);
+        }
+        for key in (0..101).step_by(4) {
+            assert_eq!(
+                expected.get_mut(&key).and_then(Vec::pop),
+                map.remove_one(&key)
+            );
+        }
+        let flat: Vec<_> = expected
+            .iter()
+            .flat_map(|(k, vs)| vs.iter().map(move |v| (k, v)))
+            .collect();
+        assert_eq!(flat.len(), map.len());
+        assert_eq!(flat, map.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_multiset() {
+        let mut stamps = TreapMultiSet::new();
+        for t in [5, 3, 5, 9, 5, 3] {
+            stamps.insert(t);
+        }
+        assert_eq!(6, stamps.len());
+        assert_eq!(3, stamps.count(&5));
+        assert_eq!(vec![&5, &5, &5], stamps.equal_range(&5).collect::<Vec<_>>());
+        assert_eq!(0, stamps.equal_range(&4).len());
+        assert_eq!(
+            vec![&3, &3, &5, &5, &5, &9],
+            stamps.iter().collect::<Vec<_>>()
+        );
+
+        assert!(stamps.remove_one(&9));
+        assert!(!stamps.remove_one(&9));
+        assert!(!stamps.contains(&9));
+        assert_eq!(3, stamps.remove_all(&5));
+        assert_eq!(2, stamps.len());
+    }
+}